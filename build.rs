@@ -0,0 +1,17 @@
+use std::process::Command;
+
+// Embed the commit the binary was built from, for /version. Builds from a
+// source archive without git leave it unset.
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    if let Some(commit) = commit.filter(|commit| !commit.is_empty()) {
+        println!("cargo:rustc-env=PIDASH_GIT_COMMIT={}", commit);
+    }
+}
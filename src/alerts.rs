@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use serde_json::{Value, json};
+use utoipa::openapi::{RefOr, schema::Schema};
+
+use crate::collector::Registry;
+use crate::config::{AlertRule, Config};
+
+// Time a webhook call may take, so a slow endpoint can't hold up the logger
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct RuleState {
+    // Consecutive samples past the threshold
+    breaches: u32,
+    firing: bool,
+    fired_at: Option<Instant>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Transition {
+    Fired,
+    Resolved,
+}
+
+// Checks each history sample against the configured thresholds and posts to
+// the webhook when a rule starts or stops firing
+pub struct Alerter {
+    client: reqwest::Client,
+    url: String,
+    cooldown: Duration,
+    rules: Vec<AlertRule>,
+    states: Mutex<Vec<RuleState>>,
+}
+
+impl Alerter {
+    // None unless a webhook URL and at least one usable rule are configured
+    pub fn new(config: &Config, collectors: &Registry) -> Option<Alerter> {
+        let Some(url) = config.alert_webhook_url.clone() else {
+            if !config.alert_rules.is_empty() {
+                warn!("Alert rules are configured without a webhook URL, alerts are off");
+            }
+            return None;
+        };
+        // A misspelled metric would otherwise never fire without a word
+        let rules: Vec<AlertRule> = config
+            .alert_rules
+            .iter()
+            .filter(|rule| match unknown_metric(collectors, &rule.metric) {
+                Some(e) => {
+                    error!("Ignoring alert rule on {}: {}", rule.metric, e);
+                    false
+                }
+                None => true,
+            })
+            .cloned()
+            .collect();
+        if rules.is_empty() {
+            warn!("An alert webhook URL is configured without usable rules, alerts are off");
+            return None;
+        }
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client");
+        info!("Checking {} alert rules on every history sample", rules.len());
+        Some(Alerter {
+            client,
+            url,
+            cooldown: Duration::from_secs(config.alert_cooldown_secs),
+            states: Mutex::new(rules.iter().map(|_| RuleState::default()).collect()),
+            rules,
+        })
+    }
+
+    // Post a message for every rule that started or stopped firing. A metric
+    // missing from the sample leaves its rule as it was.
+    pub async fn check(&self, sample: &HashMap<&'static str, Value>) {
+        let now = Instant::now();
+        let messages: Vec<String> = {
+            let mut states = self.states.lock().unwrap();
+            self.rules
+                .iter()
+                .zip(states.iter_mut())
+                .filter_map(|(rule, state)| {
+                    let value = metric_value(sample, &rule.metric)?;
+                    let transition = step(state, breached(rule, value), rule.samples, self.cooldown, now)?;
+                    Some(describe(rule, value, transition))
+                })
+                .collect()
+        };
+        if messages.is_empty() {
+            return;
+        }
+        let hostname = tokio::fs::read_to_string("/proc/sys/kernel/hostname")
+            .await
+            .map(|h| h.trim().to_string())
+            .unwrap_or_else(|_| "pidash".to_string());
+        for message in messages {
+            let text = format!("[{}] {}", hostname, message);
+            info!("Alert: {}", text);
+            // Discord reads content, Slack and Mattermost read text
+            let payload = json!({"content": text, "text": text});
+            let res = self
+                .client
+                .post(&self.url)
+                .json(&payload)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status());
+            if let Err(e) = res {
+                error!("Failed to post alert to webhook: {}", e);
+            }
+        }
+    }
+}
+
+// Why "collector.field" can't be in the logger's sample. Fields are checked
+// against the collector's schema when it lists them.
+fn unknown_metric(collectors: &Registry, metric: &str) -> Option<String> {
+    let (name, field) = metric.split_once('.').unwrap_or((metric, metric));
+    let Some(collector) = collectors.iter().find(|collector| collector.name() == name) else {
+        return Some(format!("no collector is named {}", name));
+    };
+    match collector.schema() {
+        RefOr::T(Schema::Object(object)) if !object.properties.contains_key(field) => Some(format!(
+            "{} has no field {}, expected one of {}",
+            name,
+            field,
+            object.properties.keys().cloned().collect::<Vec<_>>().join(", ")
+        )),
+        _ => None,
+    }
+}
+
+// Value of "collector.field", or of the field named like the collector
+fn metric_value(sample: &HashMap<&'static str, Value>, metric: &str) -> Option<f64> {
+    let (collector, field) = metric.split_once('.').unwrap_or((metric, metric));
+    match &sample.get(collector)?[field] {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+fn breached(rule: &AlertRule, value: f64) -> bool {
+    rule.above.is_some_and(|above| value > above) || rule.below.is_some_and(|below| value < below)
+}
+
+// Advance a rule by one sample. It fires after `samples` breaches in a row,
+// unless it fired less than `cooldown` ago, and resolves on the first sample
+// back within the threshold.
+fn step(state: &mut RuleState, breach: bool, samples: u32, cooldown: Duration, now: Instant) -> Option<Transition> {
+    if !breach {
+        state.breaches = 0;
+        if state.firing {
+            state.firing = false;
+            return Some(Transition::Resolved);
+        }
+        return None;
+    }
+    state.breaches = state.breaches.saturating_add(1);
+    let cooling_down = state.fired_at.is_some_and(|fired_at| now.duration_since(fired_at) < cooldown);
+    if state.firing || state.breaches < samples || cooling_down {
+        return None;
+    }
+    state.firing = true;
+    state.fired_at = Some(now);
+    Some(Transition::Fired)
+}
+
+fn describe(rule: &AlertRule, value: f64, transition: Transition) -> String {
+    let threshold = match (rule.above, rule.below) {
+        (Some(above), Some(below)) => format!("outside {} to {}", below, above),
+        (Some(above), None) => format!("above {}", above),
+        (None, Some(below)) => format!("below {}", below),
+        (None, None) => String::new(),
+    };
+    match transition {
+        Transition::Fired => format!(
+            "ALERT: {} is {}, {} for {} samples",
+            rule.metric, value, threshold, rule.samples
+        ),
+        Transition::Resolved => format!("RESOLVED: {} is back at {}, no longer {}", rule.metric, value, threshold),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fires_after_consecutive_breaches_with_cooldown() {
+        let mut state = RuleState::default();
+        let cooldown = Duration::from_secs(60);
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        assert_eq!(step(&mut state, true, 2, cooldown, at(0)), None);
+        assert_eq!(step(&mut state, true, 2, cooldown, at(1)), Some(Transition::Fired));
+        assert_eq!(step(&mut state, true, 2, cooldown, at(2)), None);
+        assert_eq!(step(&mut state, false, 2, cooldown, at(3)), Some(Transition::Resolved));
+        // Flapping back within the cooldown stays quiet
+        step(&mut state, true, 2, cooldown, at(4));
+        assert_eq!(step(&mut state, true, 2, cooldown, at(5)), None);
+        assert_eq!(step(&mut state, true, 2, cooldown, at(61)), Some(Transition::Fired));
+    }
+
+    #[test]
+    fn reads_metric_values() {
+        let sample = HashMap::from([
+            ("cpu_temp", json!({"cpu_temp": 81.5})),
+            ("disk_usage", json!({"percent": 93})),
+        ]);
+        assert_eq!(metric_value(&sample, "cpu_temp"), Some(81.5));
+        assert_eq!(metric_value(&sample, "disk_usage.percent"), Some(93.0));
+        assert_eq!(metric_value(&sample, "fan_speed"), None);
+    }
+
+    #[test]
+    fn rejects_unknown_metrics() {
+        let registry = Registry::new(&Config::from_env(), Default::default());
+        for metric in ["cpu_temp", "cpu_usage", "disk_usage.percent", "mem_usage.mem_used"] {
+            assert_eq!(unknown_metric(&registry, metric), None, "{}", metric);
+        }
+        for metric in ["cpu_tmp", "disk_usage.percnt", "mem_usage"] {
+            assert!(unknown_metric(&registry, metric).is_some(), "{}", metric);
+        }
+    }
+}
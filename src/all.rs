@@ -0,0 +1,57 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::Json;
+use axum::extract::State;
+use log::trace;
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::error::{ApiError, ApiResult};
+
+#[utoipa::path(
+    get,
+    path = "/all",
+    responses(
+        (status = 200, description = "Every collector's value by name, with errors of those that failed", body = AllResponse),
+        (status = 500, description = "Reading failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_all(State(state): State<AppState>) -> ApiResult<AllResponse> {
+    // Every collector's value in one response, keyed by collector name. A
+    // collector that is unsupported or fails is null and its error is listed
+    // under `errors`, so one bad sensor doesn't fail the whole response.
+    trace!("Fetching all collectors for http request");
+    // Collectors may block, cpu_usage sleeps between its two samples
+    let all = tokio::task::spawn_blocking(move || {
+        let registry = &state.collectors;
+        let mut all = AllResponse {
+            values: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        };
+        for collector in registry.iter() {
+            let result = if collector.supported() {
+                registry.collect_cached(&state.metric_cache, collector, &HashMap::new())
+            } else {
+                Err("Not supported on this board".to_string())
+            };
+            let value = result.inspect_err(|e| {
+                all.errors.insert(collector.name(), e.clone());
+            });
+            all.values.insert(collector.name(), value.ok());
+        }
+        all
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Collector task failed: {}", e)))?;
+    Ok(Json(all))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AllResponse {
+    // Collector values by name, each shaped like its own route's response
+    #[serde(flatten)]
+    values: BTreeMap<&'static str, Option<Value>>,
+    errors: BTreeMap<&'static str, String>,
+}
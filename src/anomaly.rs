@@ -0,0 +1,115 @@
+use std::collections::{HashMap, VecDeque};
+
+use axum::Json;
+use axum::extract::{Query, State};
+use log::{info, trace};
+use rusqlite::params;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::config::Config;
+use crate::db::Db;
+use crate::error::{ApiError, ApiResult};
+use crate::{AppState, HISTORY_COLUMNS, HistoryRow, history_row};
+
+// Samples a metric needs in its window before it can be flagged at all
+pub const MIN_SAMPLES: usize = 10;
+
+// Flags samples with a simple z-score test: a value is anomalous when it lies
+// more than `threshold` standard deviations away from the mean of the last
+// `window` samples of the same metric. The sample is only added to the window
+// after being tested, so a spike can't hide itself by raising the mean.
+pub struct Detector {
+    window: usize,
+    threshold: f64,
+    samples: HashMap<&'static str, VecDeque<f64>>,
+}
+
+impl Detector {
+    pub fn new(config: &Config) -> Detector {
+        Detector {
+            window: config.anomaly_window,
+            threshold: config.anomaly_threshold,
+            samples: HashMap::new(),
+        }
+    }
+
+    // Test a sample against the metric's rolling statistics and record it
+    pub fn check(&mut self, metric: &'static str, value: f64) -> bool {
+        let samples = self.samples.entry(metric).or_default();
+        let mut anomalous = false;
+        if samples.len() >= MIN_SAMPLES {
+            let n = samples.len() as f64;
+            let mean = samples.iter().sum::<f64>() / n;
+            let variance = samples.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+            let stddev = variance.sqrt();
+            // A perfectly flat metric has no spread to measure deviation against
+            if stddev > 0.0 {
+                let z = (value - mean).abs() / stddev;
+                trace!("Anomaly check for {} - value: {}, mean: {}, stddev: {}, z: {}", metric, value, mean, stddev, z);
+                anomalous = z > self.threshold;
+            }
+        }
+        samples.push_back(value);
+        while samples.len() > self.window {
+            samples.pop_front();
+        }
+        if anomalous {
+            info!("Anomalous {} sample: {}", metric, value);
+        }
+        anomalous
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/history/anomalies",
+    params(
+        ("limit" = Option<usize>, Query, description = crate::HISTORY_LIMIT),
+    ),
+    responses(
+        (status = 200, description = "Logged rows flagged as anomalous", body = AnomaliesResponse),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 500, description = "Database query failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_anomalies(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> ApiResult<AnomaliesResponse> {
+    // Return only the history rows flagged as anomalous, newest first
+    trace!("Fetching anomalous history with parameters: {:?}", params);
+    let (limit, clamped) = crate::history_limit(&params, state.config.history_max_limit).map_err(ApiError::bad_request)?;
+    let db = state.db.clone();
+    let data = tokio::task::spawn_blocking(move || query_anomalies(&db, limit))
+        .await
+        .map_err(|e| ApiError::internal(format!("History query task failed: {}", e)))?
+        .map_err(ApiError::internal)?;
+    Ok(Json(AnomaliesResponse {
+        data,
+        limit_clamped_to: clamped.then_some(limit),
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AnomaliesResponse {
+    data: Vec<HistoryRow>,
+    // As for /history, the limit was lowered to PIDASH_HISTORY_MAX_LIMIT
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit_clamped_to: Option<usize>,
+}
+
+fn query_anomalies(db: &Db, limit: usize) -> Result<Vec<HistoryRow>, String> {
+    let conn = db.open().map_err(|e| format!("Failed to open database: {}", e))?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM 'values' WHERE anomaly = 1 ORDER BY timestamp DESC LIMIT ?",
+            HISTORY_COLUMNS
+        ))
+        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let rows = stmt
+        .query_map(params![limit], history_row)
+        .map_err(|e| format!("Query execution failed: {}", e))?;
+    rows.collect::<rusqlite::Result<Vec<HistoryRow>>>()
+        .map_err(|e| format!("Error processing row: {}", e))
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use axum::extract::{Query, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http::{HeaderMap, Uri};
+use http::header::AUTHORIZATION;
+use log::warn;
+use subtle::ConstantTimeEq;
+
+use crate::AppState;
+use crate::config::Config;
+use crate::error::ApiError;
+
+pub fn check_token(headers: &HeaderMap, config: &Config) -> Result<(), ApiError> {
+    // Require `Authorization: Bearer <token>` matching PIDASH_API_TOKEN. Endpoints
+    // guarded by this stay disabled until a token is configured.
+    let expected = match &config.api_token {
+        Some(token) => token,
+        None => return Err(ApiError::forbidden("This endpoint requires PIDASH_API_TOKEN to be set")),
+    };
+    match bearer_token(headers) {
+        Some(token) if token_matches(token, expected) => Ok(()),
+        _ => Err(ApiError::unauthorized("Invalid or missing bearer token")),
+    }
+}
+
+// Constant time, so response timing doesn't reveal how much of a guess matched
+fn token_matches(provided: &str, expected: &str) -> bool {
+    provided.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+pub async fn require_token(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    // Every data endpoint needs PIDASH_API_TOKEN once it is set, as a bearer
+    // token or as ?token= for clients that can't set headers (e.g. EventSource).
+    // Without a token access stays open.
+    let query_token = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(mut params)| params.remove("token"));
+    // Handlers log their query parameters and key the cache on them, the token
+    // never reaches them
+    if query_token.is_some() {
+        *request.uri_mut() = without_token(request.uri());
+    }
+    let Some(expected) = &state.config.api_token else {
+        return next.run(request).await;
+    };
+    let provided = bearer_token(request.headers()).or(query_token.as_deref());
+    if provided.is_some_and(|token| token_matches(token, expected)) {
+        return next.run(request).await;
+    }
+    warn!("Rejected unauthenticated request to {}", request.uri().path());
+    ApiError::unauthorized("Invalid or missing bearer token").into_response()
+}
+
+fn without_token(uri: &Uri) -> Uri {
+    // Compare decoded names, so an encoded name like `%74oken` is dropped too
+    let query = uri.query().unwrap_or_default();
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    serializer.extend_pairs(form_urlencoded::parse(query.as_bytes()).filter(|(name, _)| name != "token"));
+    let query = serializer.finish();
+    let path_and_query = if query.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), query)
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::middleware;
+    use axum::routing::get;
+    use http::StatusCode;
+    use tower::ServiceExt;
+
+    async fn status(token: Option<&str>, uri: &str, header: Option<&str>) -> StatusCode {
+        let mut config = Config::from_env();
+        config.db_path = ":memory:".to_string();
+        config.api_token = token.map(str::to_string);
+        let state = AppState::new(config);
+        let app = Router::new()
+            .route("/uptime", get(|| async { "{}" }))
+            .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+            .route("/health", get(|| async { "{}" }))
+            .with_state(state);
+        let mut request = http::Request::get(uri);
+        if let Some(header) = header {
+            request = request.header(AUTHORIZATION, header);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn requires_token_only_when_configured() {
+        assert_eq!(status(None, "/uptime", None).await, StatusCode::OK);
+        assert_eq!(status(Some("secret"), "/uptime", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("secret"), "/uptime", Some("Bearer wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("secret"), "/uptime", Some("Bearer secret")).await, StatusCode::OK);
+        assert_eq!(status(Some("secret"), "/uptime?token=secret", None).await, StatusCode::OK);
+        assert_eq!(status(Some("secret"), "/uptime?%74oken=secret", None).await, StatusCode::OK);
+        assert_eq!(status(Some("secret"), "/health", None).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn strips_token_from_query() {
+        let uri: Uri = "/history?from=2024-01-01&token=secret&limit=5".parse().unwrap();
+        assert_eq!(without_token(&uri), "/history?from=2024-01-01&limit=5");
+        assert_eq!(without_token(&"/uptime?token=secret".parse().unwrap()), "/uptime");
+        assert_eq!(without_token(&"/uptime?%74oken=secret&tok%65n=secret&limit=5".parse().unwrap()), "/uptime?limit=5");
+        assert!(!token_matches("secre", "secret"));
+        assert!(token_matches("secret", "secret"));
+    }
+}
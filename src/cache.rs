@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::trace;
+use serde_json::Value;
+
+use crate::config::Config;
+
+// Recent readings by metric name, so endpoints polled by several dashboards at
+// once read the hardware at most once per TTL. Only successful readings are kept.
+pub struct MetricCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Value, Instant)>>,
+}
+
+impl MetricCache {
+    pub fn new(config: &Config) -> MetricCache {
+        MetricCache {
+            ttl: Duration::from_millis(config.cache_ttl_ms),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let entries = self.entries.lock().unwrap();
+        let (value, read_at) = entries.get(key)?;
+        if read_at.elapsed() >= self.ttl {
+            return None;
+        }
+        trace!("Serving {} from cache", key);
+        Some(value.clone())
+    }
+
+    pub fn insert(&self, key: String, value: Value) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        // Drop stale readings so keys built from query parameters don't pile up
+        entries.retain(|_, (_, read_at)| read_at.elapsed() < self.ttl);
+        entries.insert(key, (value, Instant::now()));
+    }
+
+    // Cached value of `key`, or `read` when there is none that is fresh
+    pub fn get_or_read(&self, key: String, read: impl FnOnce() -> Result<Value, String>) -> Result<Value, String> {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        let value = read()?;
+        self.insert(key, value.clone());
+        Ok(value)
+    }
+}
+
+// Cache key of a metric read with query parameters, in a stable order. A
+// ?token= never becomes part of a key
+pub fn key(name: &str, params: &HashMap<String, String>) -> String {
+    let mut params: Vec<_> = params.iter().filter(|(k, _)| *k != "token").map(|(k, v)| format!("{}={}", k, v)).collect();
+    params.sort();
+    format!("{}?{}", name, params.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn cache(ttl_ms: u64) -> MetricCache {
+        let mut config = Config::from_env();
+        config.cache_ttl_ms = ttl_ms;
+        MetricCache::new(&config)
+    }
+
+    #[test]
+    fn serves_readings_until_they_expire() {
+        let metrics = cache(50);
+        let mut reads = 0;
+        let mut read = || {
+            reads += 1;
+            Ok(json!(reads))
+        };
+        assert_eq!(metrics.get_or_read("uptime".to_string(), &mut read), Ok(json!(1)));
+        assert_eq!(metrics.get_or_read("uptime".to_string(), &mut read), Ok(json!(1)));
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(metrics.get_or_read("uptime".to_string(), &mut read), Ok(json!(2)));
+        // Failures are not cached
+        assert!(metrics.get_or_read("fan_speed".to_string(), || Err("No fan".to_string())).is_err());
+        assert_eq!(metrics.get("fan_speed"), None);
+        let disabled = cache(0);
+        disabled.insert("uptime".to_string(), json!(1));
+        assert_eq!(disabled.get("uptime"), None);
+    }
+
+    #[test]
+    fn keys_ignore_parameter_order() {
+        let params = HashMap::from([("b".to_string(), "2".to_string()), ("a".to_string(), "1".to_string())]);
+        assert_eq!(key("disk_usage", &params), "disk_usage?a=1&b=2");
+        assert_eq!(key("disk_usage", &HashMap::new()), "disk_usage?");
+        let params = HashMap::from([("token".to_string(), "secret".to_string()), ("a".to_string(), "1".to_string())]);
+        assert_eq!(key("disk_usage", &params), "disk_usage?a=1");
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+
+use axum::Json;
+use axum::extract::State;
+use log::trace;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::command::{self, CommandError};
+
+#[utoipa::path(
+    get,
+    path = "/capabilities",
+    responses(
+        (status = 200, description = "Which collectors and features this board supports", body = BTreeMap<String, Capability>),
+    )
+)]
+pub async fn get_capabilities(State(state): State<AppState>) -> Json<BTreeMap<&'static str, Capability>> {
+    // Report which collectors and tools work on this board, so a missing tool
+    // or sensor shows up here instead of as zeros or errors from the endpoints
+    trace!("Probing capabilities for http request");
+    let mut capabilities = BTreeMap::new();
+    for collector in state.collectors.iter() {
+        capabilities.insert(
+            collector.name(),
+            Capability {
+                available: collector.supported(),
+                installed: None,
+                error: None,
+            },
+        );
+    }
+    capabilities.insert("vcgencmd", tool_capability(command::vcgencmd(&state.config, &["version"]).await));
+    Json(capabilities)
+}
+
+// Whether a collector or tool works, for tools also whether it is installed
+// and why it failed
+#[derive(Serialize, ToSchema)]
+pub struct Capability {
+    available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    installed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn tool_capability(result: Result<String, CommandError>) -> Capability {
+    let (available, installed, error) = match result {
+        Ok(_) => (true, true, None),
+        Err(CommandError::NotFound(e)) => (false, false, Some(e)),
+        Err(CommandError::Failed(e)) => (false, true, Some(e)),
+    };
+    Capability {
+        available,
+        installed: Some(installed),
+        error,
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::Json;
+use axum::extract::State;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, StreamExt};
+use log::{error, trace, warn};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::config::Config;
+
+// Last successful response of a peer, served when it is momentarily unreachable
+struct PeerSnapshot {
+    data: Value,
+    last_seen: DateTime<Utc>,
+}
+
+pub struct Cluster {
+    client: reqwest::Client,
+    // Sent to peers, which are expected to share this instance's PIDASH_API_TOKEN
+    api_token: Option<String>,
+    snapshots: Mutex<HashMap<String, PeerSnapshot>>,
+}
+
+impl Cluster {
+    pub fn new(config: &Config) -> Cluster {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.cluster_timeout_ms))
+            .tls_danger_accept_invalid_certs(config.cluster_accept_invalid_certs)
+            .build()
+            .expect("Failed to build HTTP client");
+        Cluster {
+            client,
+            api_token: config.api_token.clone(),
+            snapshots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn fetch(&self, peer: &str) -> Result<Value, String> {
+        let url = format!("{}/cluster/node", peer.trim_end_matches('/'));
+        trace!("Polling cluster peer {}", url);
+        let mut request = self.client.get(&url);
+        if let Some(token) = &self.api_token {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Failed to reach peer: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("Peer responded with status {}", response.status()));
+        }
+        response
+            .json::<Value>()
+            .await
+            .map_err(|e| format!("Invalid response from peer: {}", e))
+    }
+
+    async fn poll(&self, peer: String) -> PeerStatus {
+        match self.fetch(&peer).await {
+            Ok(data) => {
+                let last_seen = Utc::now();
+                self.snapshots.lock().unwrap().insert(
+                    peer.clone(),
+                    PeerSnapshot {
+                        data: data.clone(),
+                        last_seen,
+                    },
+                );
+                PeerStatus {
+                    peer,
+                    status: "ok",
+                    error: None,
+                    last_seen: Some(last_seen.to_rfc3339()),
+                    data: Some(data),
+                }
+            }
+            Err(e) => {
+                warn!("Cluster peer {} failed: {}", peer, e);
+                let snapshots = self.snapshots.lock().unwrap();
+                let snapshot = snapshots.get(&peer);
+                PeerStatus {
+                    status: if snapshot.is_some() { "stale" } else { "unreachable" },
+                    error: Some(e),
+                    last_seen: snapshot.map(|snapshot| snapshot.last_seen.to_rfc3339()),
+                    data: snapshot.map(|snapshot| snapshot.data.clone()),
+                    peer,
+                }
+            }
+        }
+    }
+}
+
+// A peer's last response, kept as the peer sent it as peers may run another version
+#[derive(Serialize, ToSchema)]
+pub struct PeerStatus {
+    peer: String,
+    // ok, stale when an earlier response is served, or unreachable
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    last_seen: Option<String>,
+    data: Option<Value>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ClusterResponse {
+    #[serde(rename = "self")]
+    node: Option<NodeSummary>,
+    peers: Vec<PeerStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NodeSummary {
+    hostname: Option<String>,
+    serial: Option<String>,
+    cpu_usage: f64,
+    mem_used: i32,
+    mem_total: i32,
+}
+
+#[utoipa::path(
+    get,
+    path = "/cluster",
+    responses(
+        (status = 200, description = "This node and every configured peer", body = ClusterResponse),
+    )
+)]
+pub async fn get_cluster(State(state): State<AppState>) -> Json<ClusterResponse> {
+    // Poll every configured peer, at most `cluster_concurrency` at a time, so a
+    // small coordinator board never opens a connection to every peer at once
+    let peers = state.config.cluster_peers.clone();
+    trace!("Polling {} cluster peers", peers.len());
+    let cluster = &state.cluster;
+    let peers: Vec<PeerStatus> = stream::iter(peers)
+        .map(|peer| cluster.poll(peer))
+        .buffered(state.config.cluster_concurrency)
+        .collect()
+        .await;
+    Json(ClusterResponse {
+        node: node_summary(&state).await,
+        peers,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/cluster/node",
+    responses(
+        (status = 200, description = "This node's summary, as fetched by cluster peers", body = NodeSummary),
+    )
+)]
+pub async fn get_cluster_node(State(state): State<AppState>) -> Json<Option<NodeSummary>> {
+    // Summary of this board as seen by a cluster coordinator
+    trace!("Fetching cluster node summary for http request");
+    Json(node_summary(&state).await)
+}
+
+async fn node_summary(state: &AppState) -> Option<NodeSummary> {
+    // cpu_usage sleeps between its two samples
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || read_node_summary(&state))
+        .await
+        .inspect_err(|e| error!("Node summary task failed: {}", e))
+        .ok()
+}
+
+fn read_node_summary(state: &AppState) -> NodeSummary {
+    let hostname = match std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        Ok(h) => Some(h.trim().to_string()),
+        Err(e) => {
+            error!("Failed to read hostname: {}", e);
+            None
+        }
+    };
+    let (mem_total, mem_used) = crate::mem_usage();
+    NodeSummary {
+        hostname,
+        serial: state.system_info.serial.clone(),
+        cpu_usage: crate::cpu_usage(),
+        mem_used,
+        mem_total,
+    }
+}
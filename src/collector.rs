@@ -0,0 +1,1207 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::Json;
+use axum::Router;
+use axum::extract::{Query, State};
+use axum::routing::get;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{error, trace};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::openapi::{RefOr, schema::Schema};
+use utoipa::{PartialSchema, ToSchema};
+
+use crate::cache::{self, MetricCache};
+use crate::config::Config;
+use crate::thermal::TempUnit;
+use crate::error::ApiError;
+use crate::{AppState, CpuCache};
+
+// Hardware monitors, numbered in probe order which can change between boots
+const HWMON_SYSFS: &str = "/sys/class/hwmon";
+// hwmon `name` of fan drivers, pwmfan on the Pi 5 and the PWM fan overlays
+const FAN_DRIVERS: [&str; 2] = ["pwmfan", "cooling_fan"];
+const CPU_SYSFS: &str = "/sys/devices/system/cpu";
+
+// A live metric. Every registered collector gets a `/<name>` route, a section
+// in `/metrics`, an entry in `/capabilities` and `/openapi.json`, and is
+// sampled by the logger.
+pub trait Collector: Send + Sync {
+    // Route and metric name
+    fn name(&self) -> &'static str;
+    // Whether the data source exists on this board
+    fn supported(&self) -> bool;
+    fn collect(&self) -> Result<Value, String>;
+    // Variant of collect used by the route, for collectors taking query parameters
+    fn collect_with(&self, _params: &HashMap<String, String>) -> Result<Value, String> {
+        self.collect()
+    }
+    // Rejects query parameters the route can't serve, answered with a 400
+    // before anything is collected
+    fn check_params(&self, _params: &HashMap<String, String>) -> Result<(), String> {
+        Ok(())
+    }
+    // Whether a failed read is down to the query parameters, such as a path
+    // that can't be read. Answered with a 400 and not recorded as a failure.
+    fn params_at_fault(&self, _params: &HashMap<String, String>) -> bool {
+        false
+    }
+    // Answer served without reading the data source, such as an already
+    // computed value, which isn't a collection attempt
+    fn cached_value(&self, _params: &HashMap<String, String>) -> Option<Result<Value, String>> {
+        None
+    }
+    // What the route returns, and the query parameters it takes with their
+    // meaning, for /openapi.json
+    fn description(&self) -> &'static str;
+    fn params(&self) -> &'static [(&'static str, &'static str)] {
+        &[]
+    }
+    // Shape of the collected value
+    fn schema(&self) -> RefOr<Schema>;
+}
+
+// A collector's response struct as the Value the registry passes around
+fn to_json(response: impl Serialize) -> Result<Value, String> {
+    serde_json::to_value(response).map_err(|e| format!("Failed to serialize response: {}", e))
+}
+
+// Outcome history of a collector, updated on every collection attempt
+#[derive(Default, Clone)]
+pub struct CollectorStatus {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+pub struct Registry {
+    collectors: Vec<Arc<dyn Collector>>,
+    status: Mutex<HashMap<&'static str, CollectorStatus>>,
+    // The logger's own rate baseline, so logging doesn't reset the one /network
+    // clients are measured against
+    logger_network: Network,
+}
+
+// Collectors the logger doesn't sample through the registry. CPU usage comes
+// from the logger's interval delta and network from its own baseline.
+const NOT_LOGGED: [&str; 2] = ["cpu_usage", "network"];
+
+impl Registry {
+    pub fn new(config: &Config, cpu_cache: Arc<Mutex<CpuCache>>) -> Registry {
+        Registry {
+            collectors: vec![
+                Arc::new(CpuTemp {
+                    path: config.thermal_zone.clone(),
+                }),
+                Arc::new(FanSpeed {
+                    hwmon_root: HWMON_SYSFS.to_string(),
+                    path: config.fan_path.clone(),
+                }),
+                Arc::new(Uptime),
+                Arc::new(MemUsage),
+                Arc::new(SwapUsage),
+                Arc::new(DiskUsage),
+                Arc::new(CpuUsage { cache: cpu_cache }),
+                Arc::new(LoadAverage),
+                Arc::new(CpuFreq {
+                    root: CPU_SYSFS.to_string(),
+                }),
+                Arc::new(Network::default()),
+            ],
+            status: Mutex::new(HashMap::new()),
+            logger_network: Network::default(),
+        }
+    }
+
+    // Collect through the registry so the attempt is recorded in the status
+    pub fn collect(&self, collector: &Arc<dyn Collector>) -> Result<Value, String> {
+        self.record(collector.name(), collector.collect())
+    }
+
+    pub fn collect_with(&self, collector: &Arc<dyn Collector>, params: &HashMap<String, String>) -> Result<Value, String> {
+        // Rejected parameters and cache misses are the client's, the status
+        // only tracks reads of the data source
+        collector.check_params(params)?;
+        if let Some(cached) = collector.cached_value(params) {
+            return cached;
+        }
+        let result = collector.collect_with(params);
+        if result.is_err() && collector.params_at_fault(params) {
+            return result;
+        }
+        self.record(collector.name(), result)
+    }
+
+    // Reading served to endpoints, reused for the cache TTL
+    pub fn collect_cached(&self, cache: &MetricCache, collector: &Arc<dyn Collector>, params: &HashMap<String, String>) -> Result<Value, String> {
+        cache.get_or_read(cache::key(collector.name(), params), || self.collect_with(collector, params))
+    }
+
+    fn record(&self, name: &'static str, result: Result<Value, String>) -> Result<Value, String> {
+        let mut status = self.status.lock().unwrap();
+        let status = status.entry(name).or_default();
+        match &result {
+            Ok(_) => status.last_success = Some(Utc::now()),
+            Err(e) => {
+                status.last_error = Some(e.clone());
+                status.last_error_at = Some(Utc::now());
+            }
+        }
+        result
+    }
+
+    pub fn status(&self, name: &str) -> CollectorStatus {
+        self.status.lock().unwrap().get(name).cloned().unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn Collector>> {
+        self.collectors.iter()
+    }
+
+    pub fn routes(&self) -> Router<AppState> {
+        let mut router = Router::new();
+        for collector in self.iter() {
+            let collector = collector.clone();
+            router = router.route(
+                &format!("/{}", collector.name()),
+                get(move |State(state): State<AppState>, Query(params): Query<HashMap<String, String>>| async move {
+                    trace!("Fetching {} for http request", collector.name());
+                    collector.check_params(&params).map_err(ApiError::bad_request)?;
+                    // Collectors may block, cpu_usage sleeps between its two samples
+                    let task = (collector.clone(), params.clone());
+                    let value = tokio::task::spawn_blocking(move || state.collectors.collect_cached(&state.metric_cache, &task.0, &task.1))
+                        .await
+                        .map_err(|e| ApiError::internal(format!("Collector task failed: {}", e)))?
+                        .map_err(|e| if collector.params_at_fault(&params) { ApiError::bad_request(e) } else { ApiError::internal(e) })?;
+                    Ok::<_, ApiError>(Json(value))
+                }),
+            );
+        }
+        router
+    }
+
+    // Values of every supported collector that could be read, by name
+    pub fn collect_all(&self) -> HashMap<&'static str, Value> {
+        self.iter()
+            .filter(|collector| collector.supported())
+            .filter_map(|collector| Some((collector.name(), self.collect(collector).ok()?)))
+            .collect()
+    }
+
+    // Values sampled by the logger, without the collectors in NOT_LOGGED
+    pub fn collect_logged(&self) -> HashMap<&'static str, Value> {
+        let mut sample: HashMap<&'static str, Value> = self
+            .iter()
+            .filter(|collector| collector.supported() && !NOT_LOGGED.contains(&collector.name()))
+            .filter_map(|collector| Some((collector.name(), self.collect(collector).ok()?)))
+            .collect();
+        if self.logger_network.supported()
+            && let Ok(network) = self.logger_network.collect()
+        {
+            sample.insert(self.logger_network.name(), network);
+        }
+        sample
+    }
+}
+
+struct CpuTemp {
+    path: String,
+}
+
+impl Collector for CpuTemp {
+    fn name(&self) -> &'static str {
+        "cpu_temp"
+    }
+
+    fn description(&self) -> &'static str {
+        "CPU temperature from the thermal zone, with the raw millidegree reading"
+    }
+
+    fn params(&self) -> &'static [(&'static str, &'static str)] {
+        &[("unit", "c or f, Celsius by default")]
+    }
+
+    fn supported(&self) -> bool {
+        Path::new(&self.path).exists()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        self.collect_with(&HashMap::new())
+    }
+
+    fn check_params(&self, params: &HashMap<String, String>) -> Result<(), String> {
+        TempUnit::from_params(params).map(|_| ())
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        CpuTempResponse::schema()
+    }
+
+    fn collect_with(&self, params: &HashMap<String, String>) -> Result<Value, String> {
+        let unit = TempUnit::from_params(params)?;
+        // Read CPU temperature from the thermal zone file
+        trace!("Reading CPU temperature from thermal zone file");
+        let temp = std::fs::read_to_string(&self.path);
+        let temp = match temp {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Failed to read CPU temperature: {}", e);
+                return Err("Failed to read CPU temperature".to_string());
+            }
+        };
+        trace!("CPU temperature read successfully: {}", temp);
+        // The sensor file has been seen to briefly read back empty. It holds
+        // millidegrees Celsius, kept as `raw` for clients of the old format.
+        match temp.trim().parse::<i32>() {
+            Ok(temp) => to_json(CpuTempResponse {
+                cpu_temp: unit.convert_millis(temp),
+                unit: unit.symbol(),
+                raw: temp,
+            }),
+            Err(e) => {
+                error!("Failed to parse CPU temperature {:?}: {}", temp.trim(), e);
+                Err(format!("Failed to parse CPU temperature, {} contained {:?}", self.path, temp.trim()))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct CpuTempResponse {
+    cpu_temp: f64,
+    unit: &'static str,
+    // Millidegrees Celsius as read from the sensor
+    raw: i32,
+}
+
+struct FanSpeed {
+    hwmon_root: String,
+    // PIDASH_FAN_PATH, used instead of searching the hardware monitors
+    path: Option<String>,
+}
+
+// A fanN_input file and the name it is reported under
+struct Fan {
+    label: String,
+    input: PathBuf,
+}
+
+impl FanSpeed {
+    fn fans(&self) -> Vec<Fan> {
+        match &self.path {
+            Some(path) => vec![Fan {
+                label: "fan".to_string(),
+                input: PathBuf::from(path),
+            }],
+            None => find_fans(Path::new(&self.hwmon_root)),
+        }
+    }
+}
+
+impl Collector for FanSpeed {
+    fn name(&self) -> &'static str {
+        "fan_speed"
+    }
+
+    fn description(&self) -> &'static str {
+        "Speed of each fan in RPM"
+    }
+
+    fn supported(&self) -> bool {
+        self.fans().iter().any(|fan| fan.input.exists())
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        FanSpeedResponse::schema()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        // Read every fan's speed from its hardware monitor file, fans that can't
+        // be read are left out. `fan_speed` is the primary fan's.
+        trace!("Reading fan speeds from hardware monitor files");
+        let fans = self.fans();
+        if fans.is_empty() {
+            return Err("No fan found, set PIDASH_FAN_PATH to its fan1_input file".to_string());
+        }
+        let speeds: Vec<FanReading> = fans
+            .iter()
+            .filter_map(|fan| {
+                let speed = std::fs::read_to_string(&fan.input)
+                    .inspect_err(|e| error!("Failed to read fan speed from {}: {}", fan.input.display(), e))
+                    .ok()?;
+                match speed.trim().parse::<i32>() {
+                    Ok(rpm) => Some(FanReading {
+                        label: fan.label.clone(),
+                        rpm,
+                    }),
+                    Err(e) => {
+                        error!("Failed to parse fan speed from {}: {}", fan.input.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        trace!("Read {} of {} fans", speeds.len(), fans.len());
+        let Some(primary) = speeds.first() else {
+            return Err("Failed to read fan speed".to_string());
+        };
+        to_json(FanSpeedResponse {
+            fan_speed: primary.rpm,
+            fans: speeds,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct FanSpeedResponse {
+    // RPM of the primary fan
+    fan_speed: i32,
+    #[schema(inline)]
+    fans: Vec<FanReading>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FanReading {
+    label: String,
+    rpm: i32,
+}
+
+fn find_fans(hwmon_root: &Path) -> Vec<Fan> {
+    // Every fanN_input of every monitor, those of known fan drivers first so
+    // the primary fan is the board's own
+    let Ok(entries) = std::fs::read_dir(hwmon_root) else {
+        return Vec::new();
+    };
+    let mut monitors: Vec<(bool, PathBuf, String)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let name = std::fs::read_to_string(entry.path().join("name")).unwrap_or_default().trim().to_string();
+            (!FAN_DRIVERS.contains(&name.as_str()), entry.path(), name)
+        })
+        .collect();
+    monitors.sort();
+    let mut fans = Vec::new();
+    for (_, monitor, name) in monitors {
+        let Ok(files) = std::fs::read_dir(&monitor) else {
+            continue;
+        };
+        let mut indexes: Vec<u32> = files
+            .filter_map(|file| file.ok())
+            .filter_map(|file| {
+                let file = file.file_name();
+                file.to_str()?.strip_prefix("fan")?.strip_suffix("_input")?.parse::<u32>().ok()
+            })
+            .collect();
+        indexes.sort();
+        for index in indexes {
+            let label = std::fs::read_to_string(monitor.join(format!("fan{}_label", index)))
+                .map(|label| label.trim().to_string())
+                .unwrap_or_else(|_| format!("{} fan{}", name, index));
+            fans.push(Fan {
+                label,
+                input: monitor.join(format!("fan{}_input", index)),
+            });
+        }
+    }
+    trace!("Found {} fans", fans.len());
+    fans
+}
+
+struct Uptime;
+
+impl Uptime {
+    // Seconds since boot and seconds all cores together have spent idle
+    fn uptime_secs(&self) -> Result<(f64, f64), String> {
+        // Read system uptime from the /proc/uptime file
+        trace!("Reading system uptime from /proc/uptime file");
+        let uptime_str = std::fs::read_to_string("/proc/uptime");
+        let uptime_str = match uptime_str {
+            Ok(u) => u,
+            Err(e) => {
+                error!("Failed to read system uptime: {}", e);
+                return Err("Failed to read system uptime".to_string());
+            }
+        };
+        trace!("System uptime read successfully: {}", uptime_str);
+        match parse_uptime(&uptime_str) {
+            Some(times) => Ok(times),
+            None => {
+                error!("Failed to parse system uptime {:?}", uptime_str.trim());
+                Err("Failed to parse system uptime".to_string())
+            }
+        }
+    }
+}
+
+fn parse_uptime(uptime: &str) -> Option<(f64, f64)> {
+    // "350735.47 234388.90"
+    let mut fields = uptime.split_whitespace().map(|field| field.parse::<f64>().ok());
+    Some((fields.next()??, fields.next()??))
+}
+
+impl Collector for Uptime {
+    fn name(&self) -> &'static str {
+        "uptime"
+    }
+
+    fn description(&self) -> &'static str {
+        "Uptime with the boot time and idle time"
+    }
+
+    fn params(&self) -> &'static [(&'static str, &'static str)] {
+        &[("unit", "millis (default) or seconds")]
+    }
+
+    fn supported(&self) -> bool {
+        Path::new("/proc/uptime").exists()
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        UptimeResponse::schema()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        self.collect_with(&HashMap::new())
+    }
+
+    fn check_params(&self, params: &HashMap<String, String>) -> Result<(), String> {
+        match params.get("unit").map(String::as_str) {
+            None | Some("millis") | Some("seconds") => Ok(()),
+            Some(unit) => Err(format!("Invalid unit: {}, expected seconds or millis", unit)),
+        }
+    }
+
+    fn collect_with(&self, params: &HashMap<String, String>) -> Result<Value, String> {
+        let (uptime_secs, idle_secs) = self.uptime_secs()?;
+        trace!("System uptime in seconds: {}", uptime_secs);
+        // Millis stay the default so existing clients keep working
+        let uptime = match params.get("unit").map(String::as_str) {
+            None | Some("millis") => UptimeValue::Millis((uptime_secs * 1000.0).round() as i64),
+            Some("seconds") => UptimeValue::Seconds(uptime_secs),
+            Some(unit) => {
+                error!("Invalid uptime unit: {}", unit);
+                return Err(format!("Invalid unit: {}, expected seconds or millis", unit));
+            }
+        };
+        to_json(UptimeResponse {
+            uptime,
+            uptime_seconds: uptime_secs as u64,
+            uptime_human: uptime_human(uptime_secs),
+            idle_seconds: idle_secs,
+            boot_time: boot_time(Utc::now(), uptime_secs),
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct UptimeResponse {
+    // In the unit asked for
+    #[schema(inline)]
+    uptime: UptimeValue,
+    uptime_seconds: u64,
+    uptime_human: String,
+    idle_seconds: f64,
+    boot_time: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum UptimeValue {
+    Millis(i64),
+    Seconds(f64),
+}
+
+fn boot_time(now: DateTime<Utc>, uptime_secs: f64) -> String {
+    // Whole seconds, so the value doesn't jitter between requests
+    let boot = now - chrono::Duration::milliseconds((uptime_secs * 1000.0) as i64);
+    boot.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn uptime_human(uptime_secs: f64) -> String {
+    // Format as "3d 4h 12m 5s", leaving out leading zero units
+    let total_secs = uptime_secs as u64;
+    let days = total_secs / 86400;
+    let hours = total_secs / 3600 % 24;
+    let minutes = total_secs / 60 % 60;
+    let secs = total_secs % 60;
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, minutes, secs)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, secs)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+struct MemUsage;
+
+impl Collector for MemUsage {
+    fn name(&self) -> &'static str {
+        "mem_usage"
+    }
+
+    fn description(&self) -> &'static str {
+        "Memory total and used in kilobytes, with the percentage used"
+    }
+
+    fn supported(&self) -> bool {
+        Path::new("/proc/meminfo").exists()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        let (mem_total, mem_used) = crate::mem_usage();
+        to_json(MemUsageResponse {
+            mem_used,
+            mem_total,
+            mem_percent: percent(mem_used as u64, mem_total as u64),
+        })
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        MemUsageResponse::schema()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct MemUsageResponse {
+    mem_used: i32,
+    mem_total: i32,
+    mem_percent: i32,
+}
+
+struct SwapUsage;
+
+impl Collector for SwapUsage {
+    fn name(&self) -> &'static str {
+        "swap_usage"
+    }
+
+    fn description(&self) -> &'static str {
+        "Swap total and used in kilobytes, with the percentage used"
+    }
+
+    fn supported(&self) -> bool {
+        Path::new("/proc/meminfo").exists()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        let (swap_total, swap_used) = crate::swap_usage().inspect_err(|e| error!("{}", e))?;
+        to_json(SwapUsageResponse {
+            swap_used,
+            swap_total,
+            // Swap may be disabled altogether
+            swap_percent: percent(swap_used, swap_total),
+        })
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        SwapUsageResponse::schema()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct SwapUsageResponse {
+    swap_used: u64,
+    swap_total: u64,
+    swap_percent: i32,
+}
+
+struct DiskUsage;
+
+impl Collector for DiskUsage {
+    fn name(&self) -> &'static str {
+        "disk_usage"
+    }
+
+    fn description(&self) -> &'static str {
+        "Size, used and free bytes and inodes of the root filesystem, or of the one containing ?path="
+    }
+
+    fn params(&self) -> &'static [(&'static str, &'static str)] {
+        &[("path", "Report the filesystem containing this path instead, a path that is missing or can't be read is a 400")]
+    }
+
+    fn supported(&self) -> bool {
+        crate::disk_usage("/").is_ok()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        disk_usage_json("/")
+    }
+
+    // Missing and unreadable paths fail alike, with the statvfs error
+    fn params_at_fault(&self, params: &HashMap<String, String>) -> bool {
+        params.contains_key("path")
+    }
+
+    fn collect_with(&self, params: &HashMap<String, String>) -> Result<Value, String> {
+        // ?path= reports the filesystem containing that path instead of the root
+        disk_usage_json(params.get("path").map(String::as_str).unwrap_or("/"))
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        DiskUsageResponse::schema()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct DiskUsageResponse {
+    path: String,
+    total: u64,
+    used: u64,
+    free: u64,
+    total_human: String,
+    used_human: String,
+    free_human: String,
+    percent: i32,
+    inodes_total: u64,
+    inodes_used: u64,
+    inodes_free: u64,
+    inodes_percent: i32,
+}
+
+fn disk_usage_json(path: &str) -> Result<Value, String> {
+    // Read disk usage of the filesystem containing path in bytes
+    let ((total, used, free), (inodes_total, inodes_used, inodes_free)) = crate::filesystem_usage(path)?;
+    let (total, used, free) = (total * 1024, used * 1024, free * 1024);
+    to_json(DiskUsageResponse {
+        path: path.to_string(),
+        total,
+        used,
+        free,
+        total_human: bytes_human(total),
+        used_human: bytes_human(used),
+        free_human: bytes_human(free),
+        percent: percent(used, total),
+        inodes_total,
+        inodes_used,
+        inodes_free,
+        // Some filesystems (e.g. btrfs) allocate inodes dynamically and report no total
+        inodes_percent: percent(inodes_used, inodes_total),
+    })
+}
+
+// Rounded percentage of used in total, 0 rather than NaN when total is 0,
+// which the readers also return when they fail
+pub fn percent(used: u64, total: u64) -> i32 {
+    if total == 0 {
+        return 0;
+    }
+    (used as f64 / total as f64 * 100.0).round() as i32
+}
+
+fn bytes_human(bytes: u64) -> String {
+    // Binary units like df -h, e.g. "29.1G"
+    const UNITS: [&str; 6] = ["B", "K", "M", "G", "T", "P"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", size, UNITS[unit])
+    }
+}
+
+struct CpuUsage {
+    cache: Arc<Mutex<CpuCache>>,
+}
+
+impl Collector for CpuUsage {
+    fn name(&self) -> &'static str {
+        "cpu_usage"
+    }
+
+    fn description(&self) -> &'static str {
+        "CPU usage in percent over a short sample"
+    }
+
+    fn params(&self) -> &'static [(&'static str, &'static str)] {
+        &[("detailed", "true adds the share of each CPU time such as user, system and iowait"), ("mode", "cached returns the logger's last sample instead of sampling")]
+    }
+
+    fn supported(&self) -> bool {
+        Path::new("/proc/stat").exists()
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        CpuUsageResponse::schema()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        // Read CPU usage from the /proc/stat file
+        let started = Instant::now();
+        let cpu_usage = crate::cpu_usage();
+        // Report how long sampling took so clients can tune their poll rate
+        let sample_latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+        to_json(CpuUsageResponse {
+            cpu_usage,
+            sample_latency_ms: Some(sample_latency_ms),
+            ..Default::default()
+        })
+    }
+
+    fn check_params(&self, params: &HashMap<String, String>) -> Result<(), String> {
+        match params.get("detailed").map(String::as_str) {
+            None | Some("true") | Some("false") => Ok(()),
+            Some(detailed) => Err(format!("Invalid detailed: {}, expected true or false", detailed)),
+        }
+    }
+
+    fn collect_with(&self, params: &HashMap<String, String>) -> Result<Value, String> {
+        if params.get("detailed").map(String::as_str) == Some("true") {
+            // Share of user, system, iowait, steal and the other CPU times
+            let started = Instant::now();
+            let breakdown = crate::cpu_breakdown()?;
+            let idle = breakdown.get("idle").copied().unwrap_or(100.0);
+            return to_json(CpuUsageResponse {
+                cpu_usage: 100.0 - idle,
+                breakdown: Some(breakdown),
+                sample_latency_ms: Some(started.elapsed().as_secs_f64() * 1000.0),
+                ..Default::default()
+            });
+        }
+        self.cached_value(params).unwrap_or_else(|| self.collect())
+    }
+
+    fn cached_value(&self, params: &HashMap<String, String>) -> Option<Result<Value, String>> {
+        if params.get("mode").map(String::as_str) != Some("cached") {
+            return None;
+        }
+        // Served from the logger's last delta, at most one logging interval old
+        let cache = self.cache.lock().unwrap();
+        Some(
+        match (cache.usage, cache.updated_at) {
+            (Some(usage), Some(updated_at)) => to_json(CpuUsageResponse {
+                cpu_usage: usage,
+                mode: Some("cached"),
+                updated_at: Some(updated_at.to_rfc3339()),
+                ..Default::default()
+            }),
+            _ => Err("Cached CPU usage not available yet".to_string()),
+        })
+    }
+}
+
+// A sample, the detailed breakdown, or the logger's cached value with when it
+// was taken
+#[derive(Serialize, ToSchema, Default)]
+struct CpuUsageResponse {
+    cpu_usage: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    breakdown: Option<BTreeMap<String, f64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated_at: Option<String>,
+}
+
+struct LoadAverage;
+
+impl Collector for LoadAverage {
+    fn name(&self) -> &'static str {
+        "load_average"
+    }
+
+    fn description(&self) -> &'static str {
+        "1, 5 and 15 minute load averages"
+    }
+
+    fn supported(&self) -> bool {
+        Path::new("/proc/loadavg").exists()
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        LoadAverageResponse::schema()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        // Read load averages from /proc/loadavg, e.g. "0.42 0.35 0.30 2/183 4242"
+        trace!("Reading load average from /proc/loadavg file");
+        let loadavg = std::fs::read_to_string("/proc/loadavg").map_err(|e| {
+            error!("Failed to read load average: {}", e);
+            "Failed to read load average".to_string()
+        })?;
+        let load = parse_loadavg(&loadavg).ok_or_else(|| {
+            error!("Failed to parse load average: {:?}", loadavg);
+            "Failed to parse load average".to_string()
+        })?;
+        to_json(load)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct LoadAverageResponse {
+    load_1: f64,
+    load_5: f64,
+    load_15: f64,
+    // Runnable and total scheduling entities
+    running: u32,
+    total: u32,
+}
+
+fn parse_loadavg(loadavg: &str) -> Option<LoadAverageResponse> {
+    let fields: Vec<&str> = loadavg.split_whitespace().collect();
+    let (running, total) = fields.get(3)?.split_once('/')?;
+    Some(LoadAverageResponse {
+        load_1: fields.first()?.parse().ok()?,
+        load_5: fields.get(1)?.parse().ok()?,
+        load_15: fields.get(2)?.parse().ok()?,
+        running: running.parse().ok()?,
+        total: total.parse().ok()?,
+    })
+}
+
+struct CpuFreq {
+    root: String,
+}
+
+impl Collector for CpuFreq {
+    fn name(&self) -> &'static str {
+        "cpu_freq"
+    }
+
+    fn description(&self) -> &'static str {
+        "Current, minimum and maximum frequency of each CPU core"
+    }
+
+    fn supported(&self) -> bool {
+        Path::new(&self.root).join("cpu0/cpufreq").exists()
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        CpuFreqResponse::schema()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        // Read the current, minimum and maximum clock of each core from its
+        // cpufreq directory, in MHz. Cores without one (offline or no cpufreq
+        // driver) are skipped.
+        trace!("Reading CPU frequencies from {}", self.root);
+        let entries = std::fs::read_dir(&self.root).map_err(|e| {
+            error!("Failed to read {}: {}", self.root, e);
+            "Failed to read CPU frequencies".to_string()
+        })?;
+        let mut cores: Vec<CoreFreq> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let cpu = entry.file_name().to_str()?.strip_prefix("cpu")?.parse::<u32>().ok()?;
+                let cpufreq = entry.path().join("cpufreq");
+                // sysfs reports kHz
+                let mhz = |file: &str| -> Option<f64> {
+                    let khz = std::fs::read_to_string(cpufreq.join(file)).ok()?.trim().parse::<f64>().ok()?;
+                    Some(khz / 1000.0)
+                };
+                Some(CoreFreq {
+                    cpu,
+                    current_mhz: mhz("scaling_cur_freq")?,
+                    min_mhz: mhz("cpuinfo_min_freq")?,
+                    max_mhz: mhz("cpuinfo_max_freq")?,
+                })
+            })
+            .collect();
+        if cores.is_empty() {
+            return Err("No CPU frequencies available".to_string());
+        }
+        cores.sort_by_key(|core| core.cpu);
+        let average_mhz = cores.iter().map(|core| core.current_mhz).sum::<f64>() / cores.len() as f64;
+        to_json(CpuFreqResponse { cores, average_mhz })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct CpuFreqResponse {
+    #[schema(inline)]
+    cores: Vec<CoreFreq>,
+    average_mhz: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CoreFreq {
+    cpu: u32,
+    current_mhz: f64,
+    min_mhz: f64,
+    max_mhz: f64,
+}
+
+// Received and transmitted bytes by interface
+type NetCounters = HashMap<String, (u64, u64)>;
+
+// Keeps the previous /proc/net/dev read for computing rates
+#[derive(Default)]
+struct Network {
+    previous: Mutex<Option<(Instant, NetCounters)>>,
+}
+
+impl Collector for Network {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn description(&self) -> &'static str {
+        "Byte counters and transfer rates of each network interface"
+    }
+
+    fn params(&self) -> &'static [(&'static str, &'static str)] {
+        &[("include_loopback", "true includes lo")]
+    }
+
+    fn supported(&self) -> bool {
+        Path::new("/proc/net/dev").exists()
+    }
+
+    fn schema(&self) -> RefOr<Schema> {
+        Vec::<InterfaceRates>::schema()
+    }
+
+    fn collect(&self) -> Result<Value, String> {
+        self.collect_with(&HashMap::new())
+    }
+
+    fn collect_with(&self, params: &HashMap<String, String>) -> Result<Value, String> {
+        // Read per-interface byte counters from /proc/net/dev. Rates are taken
+        // against the previous read by anyone, and are null on the first read.
+        trace!("Reading network counters from /proc/net/dev file");
+        let dev = std::fs::read_to_string("/proc/net/dev").map_err(|e| {
+            error!("Failed to read network counters: {}", e);
+            "Failed to read network counters".to_string()
+        })?;
+        let counters = parse_net_dev(&dev);
+        let now = Instant::now();
+        let previous = self.previous.lock().unwrap().replace((now, counters.clone()));
+        let include_loopback = params.get("include_loopback").map(String::as_str) == Some("true");
+        let mut interfaces: Vec<(&String, &(u64, u64))> = counters
+            .iter()
+            .filter(|(interface, _)| include_loopback || interface.as_str() != "lo")
+            .collect();
+        interfaces.sort();
+        to_json(
+            interfaces
+                .into_iter()
+                .map(|(interface, &(rx_bytes, tx_bytes))| {
+                    // A counter that went backwards was reset, e.g. the interface was recreated
+                    let rate = |current: u64, select: fn(&(u64, u64)) -> u64| {
+                        let (at, counters) = previous.as_ref()?;
+                        let last = select(counters.get(interface)?);
+                        let elapsed = now.duration_since(*at).as_secs_f64();
+                        (current >= last && elapsed > 0.0).then(|| (current - last) as f64 / elapsed)
+                    };
+                    InterfaceRates {
+                        interface: interface.clone(),
+                        rx_bytes,
+                        tx_bytes,
+                        rx_rate: rate(rx_bytes, |c| c.0),
+                        tx_rate: rate(tx_bytes, |c| c.1),
+                    }
+                })
+                .collect::<Vec<InterfaceRates>>(),
+        )
+    }
+}
+
+// Rates are in bytes per second
+#[derive(Serialize, ToSchema)]
+struct InterfaceRates {
+    interface: String,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_rate: Option<f64>,
+    tx_rate: Option<f64>,
+}
+
+fn parse_net_dev(dev: &str) -> NetCounters {
+    // After two header lines: "  eth0: <8 receive fields> <8 transmit fields>",
+    // received bytes are the first receive field and sent bytes the first transmit one
+    dev.lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (interface, fields) = line.split_once(':')?;
+            let fields: Vec<&str> = fields.split_whitespace().collect();
+            let rx_bytes = fields.first()?.parse::<u64>().ok()?;
+            let tx_bytes = fields.get(8)?.parse::<u64>().ok()?;
+            Some((interface.trim().to_string(), (rx_bytes, tx_bytes)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn uptime_human_drops_leading_zero_units() {
+        assert_eq!(uptime_human(59.9), "59s");
+        assert_eq!(uptime_human(2.0 * 3600.0 + 5.0 * 60.0), "2h 5m 0s");
+        assert_eq!(uptime_human(3.0 * 86400.0 + 4.0 * 3600.0 + 12.0 * 60.0 + 5.0), "3d 4h 12m 5s");
+    }
+
+    #[test]
+    fn parses_proc_uptime() {
+        assert_eq!(parse_uptime("350735.47 234388.90\n"), Some((350735.47, 234388.90)));
+        assert_eq!(parse_uptime("350735.47\n"), None);
+        let now = DateTime::parse_from_rfc3339("2024-01-02T00:00:00.600Z").unwrap().with_timezone(&Utc);
+        assert_eq!(boot_time(now, 86400.5), "2024-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn parses_loadavg() {
+        let load = parse_loadavg("0.42 0.35 0.30 2/183 4242\n").unwrap();
+        assert_eq!(load.load_1, 0.42);
+        assert_eq!(load.load_15, 0.30);
+        assert_eq!(load.running, 2);
+        assert_eq!(load.total, 183);
+        assert!(parse_loadavg("").is_none());
+    }
+
+    #[test]
+    fn parses_net_dev() {
+        let dev = "Inter-|   Receive |  Transmit\n face |bytes packets|bytes packets\n    lo: 100 2 0 0 0 0 0 0 100 2 0 0 0 0 0 0\n  eth0: 5000 34 0 0 0 0 0 0 2400 28 0 0 0 0 0 0\n";
+        let counters = parse_net_dev(dev);
+        assert_eq!(counters["eth0"], (5000, 2400));
+        assert_eq!(counters["lo"], (100, 100));
+    }
+
+    #[test]
+    fn percent_of_zero_total_is_zero() {
+        assert_eq!(percent(0, 0), 0);
+        assert_eq!(percent(5, 0), 0);
+        assert_eq!(percent(1, 3), 33);
+        assert_eq!(percent(3, 3), 100);
+    }
+
+    #[test]
+    fn bytes_human_uses_binary_units() {
+        assert_eq!(bytes_human(512), "512B");
+        assert_eq!(bytes_human(1536), "1.5K");
+        assert_eq!(bytes_human(29 * 1024 * 1024 * 1024), "29.0G");
+    }
+
+    #[test]
+    fn reads_cpu_freq_per_core() {
+        let temp = crate::testing::temp_dir();
+        let root = temp.path();
+        for (cpu, cur) in [(0, "600000"), (1, "1500000")] {
+            let dir = root.join(format!("cpu{}/cpufreq", cpu));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("scaling_cur_freq"), cur).unwrap();
+            std::fs::write(dir.join("cpuinfo_min_freq"), "600000\n").unwrap();
+            std::fs::write(dir.join("cpuinfo_max_freq"), "1500000\n").unwrap();
+        }
+        // Directories without cpufreq, or that are not cores, are skipped
+        std::fs::create_dir_all(root.join("cpu2")).unwrap();
+        std::fs::create_dir_all(root.join("cpuidle")).unwrap();
+        let freq = CpuFreq {
+            root: root.to_string_lossy().to_string(),
+        };
+        let value = freq.collect().unwrap();
+        assert_eq!(value["cores"].as_array().unwrap().len(), 2);
+        assert_eq!(value["cores"][1]["current_mhz"], 1500.0);
+        assert_eq!(value["cores"][0]["max_mhz"], 1500.0);
+        assert_eq!(value["average_mhz"], 1050.0);
+    }
+
+    #[test]
+    fn cpu_temp_reports_degrees() {
+        let dir = crate::testing::temp_dir();
+        let path = dir.path().join("temp");
+        std::fs::write(&path, "48123\n").unwrap();
+        let sensor = CpuTemp {
+            path: path.to_string_lossy().to_string(),
+        };
+        let celsius = sensor.collect().unwrap();
+        let fahrenheit = sensor.collect_with(&HashMap::from([("unit".to_string(), "f".to_string())])).unwrap();
+        assert_eq!(celsius, json!({"cpu_temp": 48.123, "unit": "C", "raw": 48123}));
+        assert_eq!(fahrenheit["cpu_temp"], 118.621);
+        assert_eq!(fahrenheit["raw"], 48123);
+    }
+
+    #[test]
+    fn finds_every_fan() {
+        let temp = crate::testing::temp_dir();
+        let root = temp.path();
+        let monitors = [
+            ("hwmon0", "cpu_thermal", vec![]),
+            ("hwmon1", "nct6775", vec![("fan1", "900", Some("Case Fan")), ("fan2", "0", None)]),
+            ("hwmon3", "pwmfan", vec![("fan1", "2450", None)]),
+        ];
+        for (monitor, name, fans) in monitors {
+            let dir = root.join(monitor);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("name"), format!("{}\n", name)).unwrap();
+            for (fan, rpm, label) in fans {
+                std::fs::write(dir.join(format!("{}_input", fan)), rpm).unwrap();
+                if let Some(label) = label {
+                    std::fs::write(dir.join(format!("{}_label", fan)), label).unwrap();
+                }
+            }
+        }
+        let fan = FanSpeed {
+            hwmon_root: root.to_string_lossy().to_string(),
+            path: None,
+        };
+        let value = fan.collect().unwrap();
+        // The known fan driver is the primary fan
+        assert_eq!(value["fan_speed"], 2450);
+        assert_eq!(
+            value["fans"],
+            json!([
+                {"label": "pwmfan fan1", "rpm": 2450},
+                {"label": "Case Fan", "rpm": 900},
+                {"label": "nct6775 fan2", "rpm": 0}
+            ])
+        );
+        // PIDASH_FAN_PATH skips the search
+        let configured = FanSpeed {
+            hwmon_root: root.to_string_lossy().to_string(),
+            path: Some(root.join("hwmon9/fan1_input").to_string_lossy().to_string()),
+        };
+        assert!(!configured.supported());
+        drop(temp);
+        assert!(fan.collect().is_err());
+    }
+
+    #[test]
+    fn status_ignores_client_errors() {
+        let registry = Registry::new(&Config::from_env(), Arc::new(Mutex::new(Default::default())));
+        let cpu_usage = registry.iter().find(|collector| collector.name() == "cpu_usage").unwrap();
+        for (key, value) in [("mode", "cached"), ("detailed", "maybe")] {
+            let params = HashMap::from([(key.to_string(), value.to_string())]);
+            assert!(registry.collect_with(cpu_usage, &params).is_err());
+        }
+        let status = registry.status("cpu_usage");
+        assert!(status.last_error.is_none() && status.last_success.is_none());
+        registry.collect_with(cpu_usage, &HashMap::new()).unwrap();
+        assert!(registry.status("cpu_usage").last_success.is_some());
+    }
+
+    #[test]
+    fn disk_usage_rejects_unusable_path() {
+        let registry = Registry::new(&Config::from_env(), Default::default());
+        let disk_usage = registry.iter().find(|collector| collector.name() == "disk_usage").unwrap();
+        let params = HashMap::from([("path".to_string(), "/no/such/mount".to_string())]);
+        let error = registry.collect_with(disk_usage, &params).unwrap_err();
+        assert!(error.starts_with("Failed to statvfs /no/such/mount"), "{}", error);
+        assert!(disk_usage.params_at_fault(&params));
+        assert!(registry.status("disk_usage").last_error.is_none());
+        let params = HashMap::from([("path".to_string(), "/".to_string())]);
+        assert!(registry.collect_with(disk_usage, &params).is_ok());
+    }
+
+    #[test]
+    fn uptime_rejects_unknown_unit() {
+        let params = HashMap::from([("unit".to_string(), "fortnights".to_string())]);
+        assert!(Uptime.check_params(&params).is_err());
+        assert!(Uptime.collect_with(&params).is_err());
+    }
+}
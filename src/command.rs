@@ -0,0 +1,61 @@
+use std::fmt;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use log::{trace, warn};
+use tokio::process::Command;
+
+use crate::config::Config;
+
+// Why running an external tool failed, so users can tell a missing tool they
+// need to install from one that is installed but not working
+pub enum CommandError {
+    NotFound(String),
+    Failed(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::NotFound(msg) | CommandError::Failed(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+pub async fn run(program: &str, args: &[&str], timeout: Duration) -> Result<String, CommandError> {
+    trace!("Running {} {}", program, args.join(" "));
+    // kill_on_drop reaps a hung process once the timeout drops its future
+    let output = Command::new(program).args(args).kill_on_drop(true).output();
+    let output = tokio::time::timeout(timeout, output)
+        .await
+        .map_err(|_| CommandError::Failed(format!("{} timed out after {}ms", program, timeout.as_millis())))?
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => CommandError::NotFound(format!("{} not found, is it installed?", program)),
+            _ => CommandError::Failed(format!("Failed to run {}: {}", program, e)),
+        })?;
+    if !output.status.success() {
+        return Err(CommandError::Failed(format!(
+            "{} failed with status: {}",
+            program, output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+pub async fn vcgencmd(config: &Config, args: &[&str]) -> Result<String, CommandError> {
+    // The VideoCore mailbox is sometimes briefly busy, so a failed or hung call
+    // is retried once before giving up. A missing vcgencmd isn't retried.
+    let timeout = Duration::from_millis(config.vcgencmd_timeout_ms);
+    match run("vcgencmd", args, timeout).await {
+        Err(CommandError::Failed(e)) => {
+            warn!("{}, retrying", e);
+            run("vcgencmd", args, timeout).await
+        }
+        result => result,
+    }
+}
+
+// Value of vcgencmd's `key=value` output, e.g. "47.2'C" for `temp` in "temp=47.2'C"
+pub fn vcgencmd_field<'a>(stdout: &'a str, key: &str) -> Option<&'a str> {
+    stdout.trim().strip_prefix(key)?.strip_prefix('=')
+}
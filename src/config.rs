@@ -0,0 +1,385 @@
+use std::str::FromStr;
+
+use log::error;
+use serde::Deserialize;
+
+// Config file read when neither --config nor PIDASH_CONFIG names one
+const DEFAULT_CONFIG_FILE: &str = "pidash.toml";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // Path of the SQLite history database, `:memory:` keeps history in memory only
+    pub db_path: String,
+    // Block device (as named in /proc/diskstats) whose writes are tracked for wear
+    pub sd_device: String,
+    // Rated write endurance of the card in terabytes written
+    pub card_endurance_tbw: f64,
+    // Base URLs of other PiDash instances polled by /cluster
+    pub cluster_peers: Vec<String>,
+    // Maximum number of peers polled at the same time
+    pub cluster_concurrency: usize,
+    // Per-peer request timeout in milliseconds
+    pub cluster_timeout_ms: u64,
+    // Accept self-signed peer certificates
+    pub cluster_accept_invalid_certs: bool,
+    // Hours of history returned by /history when neither from nor to is given, 0 for all
+    pub history_default_hours: u64,
+    // Largest number of rows /history returns in one response
+    pub history_max_limit: usize,
+    // Bearer token required by protected endpoints
+    pub api_token: Option<String>,
+    // Directory compressed history exports are written to
+    pub export_dir: String,
+    // Seconds between history rows
+    pub log_interval_secs: u64,
+    // Days of history kept, older rows are deleted by the logger. 0 keeps everything.
+    pub retention_days: u64,
+    // Seconds to wait after startup before the first history row is written
+    pub startup_delay_secs: u64,
+    // Number of recent samples the anomaly mean and standard deviation are computed over
+    pub anomaly_window: usize,
+    // Standard deviations from the rolling mean beyond which a sample is flagged
+    pub anomaly_threshold: f64,
+    // Mountpoints whose usage is logged to the disk_history table, empty logs only the root disk
+    pub history_mounts: Vec<String>,
+    // Time a single vcgencmd call may take before it is killed
+    pub vcgencmd_timeout_ms: u64,
+    // Fan speed file, found among the hardware monitors when unset
+    pub fan_path: Option<String>,
+    // Temperature file of the CPU thermal zone, in millidegrees Celsius
+    pub thermal_zone: String,
+    // Address the HTTPS server listens on, validated at startup
+    pub listen_addr: String,
+    // Origins allowed to call the API from a browser, `*` for any
+    pub cors_origins: Vec<String>,
+    // Requests per second each client IP may sustain, 0 disables the limit
+    pub rate_limit_per_sec: f64,
+    // Requests a client may make in a burst above the sustained rate
+    pub rate_limit_burst: u32,
+    // Milliseconds endpoints reuse a reading before reading the hardware again, 0 disables the cache
+    pub cache_ttl_ms: u64,
+    // Directory of the built front-end served at /
+    pub static_dir: String,
+    // Milliseconds a request may take before it is logged as slow, 0 disables the warning
+    pub slow_request_ms: u64,
+    // InfluxDB write URL each history sample is pushed to
+    pub influx_url: Option<String>,
+    // InfluxDB API token sent with every push
+    pub influx_token: Option<String>,
+    // MQTT broker each history sample is published to
+    pub mqtt_host: Option<String>,
+    pub mqtt_port: u16,
+    pub mqtt_username: Option<String>,
+    pub mqtt_password: Option<String>,
+    // Topic prefix, collectors are published to <prefix>/<collector>
+    pub mqtt_topic: String,
+    // Home Assistant discovery prefix, empty disables discovery
+    pub mqtt_discovery_prefix: String,
+    // Zone /history converts timestamps to unless the request names one with ?tz=
+    pub timezone: chrono_tz::Tz,
+    // Enables the reboot and shutdown endpoints, only when set to exactly true
+    pub allow_control: bool,
+    // Webhook alerts are posted to, alerting is off without it
+    pub alert_webhook_url: Option<String>,
+    // Seconds before a rule that fired may fire again
+    pub alert_cooldown_secs: u64,
+    // Thresholds from the [alerts] section of the config file
+    pub alert_rules: Vec<AlertRule>,
+}
+
+// Optional pidash.toml, every value can still be overridden by its environment variable
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub server: ServerSection,
+    pub database: DatabaseSection,
+    pub history: HistorySection,
+    pub thermal: ThermalSection,
+    pub alerts: AlertsSection,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    // Full ip:port, takes precedence over bind_addr and port
+    pub listen: Option<String>,
+    pub bind_addr: String,
+    pub port: u16,
+}
+
+impl Default for ServerSection {
+    fn default() -> ServerSection {
+        ServerSection {
+            listen: None,
+            bind_addr: "0.0.0.0".to_string(),
+            port: 80,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSection {
+    pub path: String,
+}
+
+impl Default for DatabaseSection {
+    fn default() -> DatabaseSection {
+        DatabaseSection {
+            path: "history.db".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistorySection {
+    pub log_interval_secs: u64,
+    pub retention_days: u64,
+}
+
+impl Default for HistorySection {
+    fn default() -> HistorySection {
+        HistorySection {
+            log_interval_secs: crate::LOG_INTERVAL_SECS,
+            retention_days: 30,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThermalSection {
+    pub zone: String,
+}
+
+impl Default for ThermalSection {
+    fn default() -> ThermalSection {
+        ThermalSection {
+            zone: "/sys/class/thermal/thermal_zone0/temp".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsSection {
+    pub webhook_url: Option<String>,
+    pub cooldown_secs: u64,
+    pub rules: Vec<AlertRule>,
+}
+
+impl Default for AlertsSection {
+    fn default() -> AlertsSection {
+        AlertsSection {
+            webhook_url: None,
+            cooldown_secs: 3600,
+            rules: Vec::new(),
+        }
+    }
+}
+
+// A threshold on one collector field, e.g. metric = "disk_usage.percent" and
+// above = 90. A bare collector name means its field of the same name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub metric: String,
+    pub above: Option<f64>,
+    pub below: Option<f64>,
+    // Consecutive history samples past the threshold before the alert fires
+    #[serde(default = "default_alert_samples")]
+    pub samples: u32,
+}
+
+fn default_alert_samples() -> u32 {
+    3
+}
+
+impl FileConfig {
+    // Read the file named by --config or PIDASH_CONFIG. Without either a missing
+    // pidash.toml just means defaults, a named file has to exist.
+    pub fn load(args: &[String]) -> Result<(Option<String>, FileConfig), String> {
+        let named = args
+            .iter()
+            .position(|arg| arg == "--config")
+            .map(|i| args.get(i + 1).cloned().ok_or("--config needs a file path"))
+            .transpose()?
+            .or_else(|| std::env::var("PIDASH_CONFIG").ok().filter(|p| !p.is_empty()));
+        let path = match named {
+            Some(path) => path,
+            None if std::path::Path::new(DEFAULT_CONFIG_FILE).exists() => DEFAULT_CONFIG_FILE.to_string(),
+            None => return Ok((None, FileConfig::default())),
+        };
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+        let file = FileConfig::parse(&text).map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+        Ok((Some(path), file))
+    }
+
+    pub fn parse(text: &str) -> Result<FileConfig, String> {
+        let file: FileConfig = toml::from_str(text).map_err(|e| e.to_string())?;
+        for rule in &file.alerts.rules {
+            if rule.above.is_none() && rule.below.is_none() {
+                return Err(format!("Alert rule for {} needs above or below", rule.metric));
+            }
+            if rule.samples == 0 {
+                return Err(format!("Alert rule for {} needs at least 1 sample", rule.metric));
+            }
+        }
+        Ok(file)
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        Config::from_file(FileConfig::default())
+    }
+
+    // File values with the environment variables applied on top
+    pub fn from_file(file: FileConfig) -> Config {
+        let log_interval_secs = match env_or("PIDASH_LOG_INTERVAL_SECS", file.history.log_interval_secs) {
+            0 => {
+                error!("The log interval must be positive, using default");
+                crate::LOG_INTERVAL_SECS
+            }
+            secs => secs,
+        };
+        Config {
+            db_path: env_or("PIDASH_DB_PATH", file.database.path),
+            sd_device: env_or("PIDASH_SD_DEVICE", "mmcblk0".to_string()),
+            card_endurance_tbw: env_or("PIDASH_CARD_ENDURANCE_TBW", 10.0),
+            cluster_peers: env_list("PIDASH_CLUSTER_PEERS"),
+            cluster_concurrency: env_or("PIDASH_CLUSTER_CONCURRENCY", 4).max(1),
+            cluster_timeout_ms: env_or("PIDASH_CLUSTER_TIMEOUT_MS", 2000),
+            cluster_accept_invalid_certs: env_or("PIDASH_CLUSTER_ACCEPT_INVALID_CERTS", false),
+            history_default_hours: env_or("PIDASH_HISTORY_DEFAULT_HOURS", 24),
+            history_max_limit: env_or("PIDASH_HISTORY_MAX_LIMIT", 10000).max(1),
+            api_token: std::env::var("PIDASH_API_TOKEN").ok().filter(|t| !t.is_empty()),
+            export_dir: env_or("PIDASH_EXPORT_DIR", "exports".to_string()),
+            log_interval_secs,
+            retention_days: env_or("PIDASH_RETENTION_DAYS", file.history.retention_days),
+            startup_delay_secs: env_or("PIDASH_STARTUP_DELAY_SECS", log_interval_secs),
+            // A smaller window could never hold enough samples to flag anything
+            anomaly_window: env_or("PIDASH_ANOMALY_WINDOW", 60).max(crate::anomaly::MIN_SAMPLES),
+            anomaly_threshold: env_or("PIDASH_ANOMALY_THRESHOLD", 3.0),
+            history_mounts: env_list("PIDASH_HISTORY_MOUNTS"),
+            vcgencmd_timeout_ms: env_or("PIDASH_VCGENCMD_TIMEOUT_MS", 2000),
+            fan_path: std::env::var("PIDASH_FAN_PATH").ok().filter(|p| !p.is_empty()),
+            thermal_zone: env_or("PIDASH_THERMAL_ZONE", file.thermal.zone),
+            listen_addr: listen_addr(file.server),
+            cors_origins: env_list("PIDASH_CORS_ORIGINS"),
+            rate_limit_per_sec: env_or("PIDASH_RATE_LIMIT", 10.0),
+            rate_limit_burst: env_or("PIDASH_RATE_LIMIT_BURST", 20),
+            cache_ttl_ms: env_or("PIDASH_CACHE_TTL_MS", 5000),
+            static_dir: env_or("PIDASH_STATIC_DIR", "frontend/dist".to_string()),
+            slow_request_ms: env_or("PIDASH_SLOW_REQUEST_MS", 500),
+            influx_url: std::env::var("PIDASH_INFLUX_URL").ok().filter(|u| !u.is_empty()),
+            influx_token: std::env::var("PIDASH_INFLUX_TOKEN").ok().filter(|t| !t.is_empty()),
+            mqtt_host: std::env::var("PIDASH_MQTT_HOST").ok().filter(|h| !h.is_empty()),
+            mqtt_port: env_or("PIDASH_MQTT_PORT", 1883),
+            mqtt_username: std::env::var("PIDASH_MQTT_USERNAME").ok().filter(|u| !u.is_empty()),
+            mqtt_password: std::env::var("PIDASH_MQTT_PASSWORD").ok().filter(|p| !p.is_empty()),
+            mqtt_topic: env_or("PIDASH_MQTT_TOPIC", "pidash".to_string()),
+            mqtt_discovery_prefix: env_or("PIDASH_MQTT_DISCOVERY_PREFIX", "homeassistant".to_string()),
+            timezone: env_or("PIDASH_TZ", chrono_tz::UTC),
+            allow_control: std::env::var("PIDASH_ALLOW_CONTROL").is_ok_and(|v| v == "true"),
+            alert_webhook_url: std::env::var("PIDASH_ALERT_WEBHOOK_URL")
+                .ok()
+                .or(file.alerts.webhook_url)
+                .filter(|u| !u.is_empty()),
+            alert_cooldown_secs: env_or("PIDASH_ALERT_COOLDOWN_SECS", file.alerts.cooldown_secs),
+            alert_rules: file.alerts.rules,
+        }
+    }
+
+    // Debug output with the API token hidden, for the startup log
+    pub fn redacted(&self) -> String {
+        let mut config = self.clone();
+        if config.api_token.is_some() {
+            config.api_token = Some("<redacted>".to_string());
+        }
+        if config.influx_token.is_some() {
+            config.influx_token = Some("<redacted>".to_string());
+        }
+        if config.mqtt_password.is_some() {
+            config.mqtt_password = Some("<redacted>".to_string());
+        }
+        // Slack and Discord webhook URLs carry their secret in the path
+        if config.alert_webhook_url.is_some() {
+            config.alert_webhook_url = Some("<redacted>".to_string());
+        }
+        format!("{:?}", config)
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(value) => match value.trim().parse::<T>() {
+            Ok(v) => v,
+            Err(_) => {
+                error!("Invalid value for {}: {:?}, using default", key, value);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+// PIDASH_LISTEN takes a full address, otherwise it is built from
+// PIDASH_BIND_ADDR and PIDASH_PORT. The file's listen is used when none of them
+// are set.
+fn listen_addr(server: ServerSection) -> String {
+    if let Ok(listen) = std::env::var("PIDASH_LISTEN") {
+        return listen.trim().to_string();
+    }
+    if let Some(listen) = server.listen
+        && std::env::var_os("PIDASH_BIND_ADDR").is_none()
+        && std::env::var_os("PIDASH_PORT").is_none()
+    {
+        return listen.trim().to_string();
+    }
+    let host = env_or("PIDASH_BIND_ADDR", server.bind_addr);
+    let port: u16 = env_or("PIDASH_PORT", server.port);
+    // IPv6 addresses need brackets to be followed by a port
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+fn env_list(key: &str) -> Vec<String> {
+    std::env::var(key)
+        .map(|value| {
+            value
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_partial_config_file() {
+        let file = FileConfig::parse(
+            "[database]\npath = \"/mnt/usb/history.db\"\n\n[history]\nretention_days = 7\n\n[server]\nlisten = \"127.0.0.1:8443\"\n",
+        )
+        .unwrap();
+        assert_eq!(file.database.path, "/mnt/usb/history.db");
+        assert_eq!(file.history.retention_days, 7);
+        // Keys left out keep their defaults
+        assert_eq!(file.history.log_interval_secs, crate::LOG_INTERVAL_SECS);
+        assert_eq!(file.server.listen.as_deref(), Some("127.0.0.1:8443"));
+        assert_eq!(file.thermal.zone, "/sys/class/thermal/thermal_zone0/temp");
+        assert!(FileConfig::parse("[history]\nretension_days = 7\n").is_err());
+        let file = FileConfig::parse("[[alerts.rules]]\nmetric = \"cpu_temp\"\nabove = 80\n").unwrap();
+        assert_eq!(file.alerts.rules[0].samples, 3);
+        assert!(FileConfig::parse("[[alerts.rules]]\nmetric = \"cpu_temp\"\n").is_err());
+        assert!(FileConfig::parse("[history]\nretention_days = \"week\"\n").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use axum::Json;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum_client_ip::ClientIp;
+use http::{HeaderMap, StatusCode};
+use log::{error, warn};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::auth;
+use crate::command;
+use crate::error::ApiError;
+
+// Pause between answering the request and running the command, so the
+// response reaches the client before the network goes down
+const COMMAND_DELAY: Duration = Duration::from_secs(1);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[utoipa::path(
+    post,
+    path = "/system/reboot",
+    responses(
+        (status = 202, description = "Reboot scheduled", body = ControlResponse),
+        (status = 401, description = "Invalid or missing bearer token", body = crate::error::ErrorBody),
+        (status = 403, description = "System control or the API token is not enabled", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn post_reboot(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    control(&state, &headers, ip, "reboot").await
+}
+
+#[utoipa::path(
+    post,
+    path = "/system/shutdown",
+    responses(
+        (status = 202, description = "Shutdown scheduled", body = ControlResponse),
+        (status = 401, description = "Invalid or missing bearer token", body = crate::error::ErrorBody),
+        (status = 403, description = "System control or the API token is not enabled", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn post_shutdown(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    control(&state, &headers, ip, "poweroff").await
+}
+
+async fn control(
+    state: &AppState,
+    headers: &HeaderMap,
+    ip: std::net::IpAddr,
+    action: &'static str,
+) -> Result<(StatusCode, Json<ControlResponse>), ApiError> {
+    // Needs PIDASH_ALLOW_CONTROL=true and the bearer token, a ?token= in a link
+    // is not enough
+    if !state.config.allow_control {
+        warn!("Rejected {} request from {}, system control is disabled", action, ip);
+        return Err(ApiError::forbidden("System control is disabled, set PIDASH_ALLOW_CONTROL=true to enable it"));
+    }
+    auth::check_token(headers, &state.config).inspect_err(|e| warn!("Rejected {} request from {}: {}", action, ip, e.message()))?;
+    warn!("{} requested by {}", action, ip);
+    tokio::spawn(async move {
+        tokio::time::sleep(COMMAND_DELAY).await;
+        if let Err(e) = command::run("systemctl", &[action], COMMAND_TIMEOUT).await {
+            error!("Failed to {}: {}", action, e);
+        }
+    });
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ControlResponse {
+            action,
+            requested_by: ip.to_string(),
+            delay_secs: COMMAND_DELAY.as_secs(),
+        }),
+    ))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ControlResponse {
+    // The systemctl command, reboot or poweroff
+    action: &'static str,
+    requested_by: String,
+    delay_secs: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use http::header::AUTHORIZATION;
+
+    fn state(allow_control: bool, token: Option<&str>) -> AppState {
+        let mut config = Config::from_env();
+        config.db_path = ":memory:".to_string();
+        config.allow_control = allow_control;
+        config.api_token = token.map(str::to_string);
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn control_needs_opt_in_and_token() {
+        let ip = "192.0.2.1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        // The command itself is never reached, every request here is rejected
+        let rejected = |result: Result<_, ApiError>| result.err().map(|e| e.into_response().status());
+        assert_eq!(rejected(control(&state(false, Some("secret")), &headers, ip, "reboot").await), Some(StatusCode::FORBIDDEN));
+        assert_eq!(rejected(control(&state(true, None), &headers, ip, "reboot").await), Some(StatusCode::FORBIDDEN));
+        assert_eq!(
+            rejected(control(&state(true, Some("other")), &headers, ip, "reboot").await),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use http::{HeaderValue, Method, header};
+use log::{error, info};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::config::Config;
+
+// CORS for dashboards served from another origin. `*` allows any origin, no
+// origins sends no CORS headers so browsers only allow same-origin requests.
+pub fn layer(config: &Config) -> CorsLayer {
+    let layer = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]);
+    if config.cors_origins.iter().any(|origin| origin == "*") {
+        info!("Allowing cross-origin requests from any origin");
+        return layer.allow_origin(Any);
+    }
+    let origins: Vec<HeaderValue> = config
+        .cors_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin.trim_end_matches('/')) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                error!("Invalid CORS origin {:?}, ignoring it", origin);
+                None
+            }
+        })
+        .collect();
+    if !origins.is_empty() {
+        info!("Allowing cross-origin requests from {}", config.cors_origins.join(", "));
+    }
+    layer.allow_origin(AllowOrigin::list(origins))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::routing::get;
+    use http::Request;
+    use tower::ServiceExt;
+
+    async fn preflight(origins: &[&str], origin: &str) -> Option<String> {
+        let mut config = Config::from_env();
+        config.cors_origins = origins.iter().map(|o| o.to_string()).collect();
+        let app = Router::new().route("/all", get(|| async { "{}" })).layer(layer(&config));
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/all")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn answers_preflight_for_allowed_origins_only() {
+        let dev = "http://localhost:5173";
+        assert_eq!(preflight(&["http://localhost:5173/"], dev).await.as_deref(), Some(dev));
+        assert_eq!(preflight(&["http://pi.local"], dev).await, None);
+        assert_eq!(preflight(&[], dev).await, None);
+        assert_eq!(preflight(&["*"], dev).await.as_deref(), Some("*"));
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::Path;
+
+use log::{info, warn};
+use tower_http::services::{ServeDir, ServeFile};
+
+use crate::config::Config;
+
+// Built front-end served for every path no API route matches. Paths that are
+// not files get index.html so the front-end's own routes survive a reload.
+pub fn service(config: &Config) -> ServeDir<ServeFile> {
+    let dir = Path::new(&config.static_dir);
+    if dir.join("index.html").is_file() {
+        info!("Serving the dashboard from {}", dir.display());
+    } else {
+        warn!("No index.html in {}, only the API is served", dir.display());
+    }
+    ServeDir::new(dir).fallback(ServeFile::new(dir.join("index.html")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::routing::get;
+    use http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn serves_files_behind_the_api() {
+        let temp = crate::testing::temp_dir();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("index.html"), "<html>dashboard</html>").unwrap();
+        std::fs::write(dir.join("assets/app.js"), "console.log(1)").unwrap();
+        let mut config = Config::from_env();
+        config.static_dir = dir.to_string_lossy().into_owned();
+        let app = Router::new()
+            .route("/uptime", get(|| async { "api" }))
+            .fallback_service(service(&config));
+        let body = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+        assert_eq!(body("/uptime").await, "api");
+        assert_eq!(body("/").await, "<html>dashboard</html>");
+        assert_eq!(body("/assets/app.js").await, "console.log(1)");
+        // A front-end route gets index.html
+        assert_eq!(body("/settings").await, "<html>dashboard</html>");
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use log::{error, info};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+
+use crate::{disk_history, disk_wear, network_history};
+
+// Connections kept open to a history database file. WAL mode lets the
+// handlers read while the logger writes.
+const POOL_SIZE: u32 = 4;
+// How long a connection waits for another's write lock before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type DbConnection = PooledConnection<SqliteConnectionManager>;
+
+// Shared pool of connections to the history database
+pub struct Db {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl Db {
+    pub fn new(path: &str) -> Result<Db, r2d2::Error> {
+        let pool = if path == ":memory:" || path == "sqlite::memory:" {
+            // Every in-memory connection is a database of its own, so the pool
+            // holds exactly one connection and never closes it
+            info!("Using in-memory history database, history is lost on exit");
+            Pool::builder()
+                .max_size(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .build(SqliteConnectionManager::memory())?
+        } else {
+            let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+                conn.busy_timeout(BUSY_TIMEOUT)?;
+                conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+            });
+            Pool::builder().max_size(POOL_SIZE).build(manager)?
+        };
+        Ok(Db { pool })
+    }
+
+    pub fn open(&self) -> Result<DbConnection, r2d2::Error> {
+        self.pool.get()
+    }
+}
+
+pub fn create_schema(conn: &Connection) {
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS 'values' (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        cpu_usage FLOAT,
+        mem_total INTEGER,
+        mem_used INTEGER,
+        disk_total INTEGER,
+        disk_used INTEGER,
+        disk_free INTEGER,
+        timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+        anomaly BOOLEAN NOT NULL DEFAULT 0,
+        cpu_temp INTEGER,
+        fan_speed INTEGER
+        )",
+        (),
+    ) {
+        Ok(_) => {}
+        Err(err) => {
+            error!("Failed to create table: {}", err);
+        }
+    }
+    for (column, definition) in [("anomaly", "BOOLEAN NOT NULL DEFAULT 0"), ("cpu_temp", "INTEGER"), ("fan_speed", "INTEGER")] {
+        if let Err(err) = add_column(conn, column, definition) {
+            error!("Failed to add {} column: {}", column, err);
+        }
+    }
+    if let Err(err) = conn.execute(disk_history::CREATE_TABLE, ()) {
+        error!("Failed to create disk_history table: {}", err);
+    }
+    if let Err(err) = conn.execute(network_history::CREATE_TABLE, ()) {
+        error!("Failed to create network_history table: {}", err);
+    }
+    if let Err(err) = network_history::add_values_id(conn) {
+        error!("Failed to add values_id column to network_history: {}", err);
+    }
+    if let Err(err) = conn.execute("CREATE INDEX IF NOT EXISTS idx_network_history_values_id ON network_history(values_id)", ()) {
+        error!("Failed to create idx_network_history_values_id index: {}", err);
+    }
+    if let Err(err) = conn.execute(disk_wear::CREATE_TABLE, ()) {
+        error!("Failed to create disk_writes table: {}", err);
+    }
+    // Range queries by the history endpoints and pruning by retention all
+    // filter on timestamp
+    for (index, table) in [
+        ("idx_values_timestamp", "'values'"),
+        ("idx_disk_history_timestamp", "disk_history"),
+        ("idx_network_history_timestamp", "network_history"),
+    ] {
+        let sql = format!("CREATE INDEX IF NOT EXISTS {} ON {}(timestamp)", index, table);
+        if let Err(err) = conn.execute(&sql, ()) {
+            error!("Failed to create {} index: {}", index, err);
+        }
+    }
+}
+
+// Adds a column to history databases created before it existed
+fn add_column(conn: &Connection, column: &str, definition: &str) -> rusqlite::Result<()> {
+    let has_column = conn
+        .prepare("SELECT 1 FROM pragma_table_info('values') WHERE name = ?")?
+        .exists([column])?;
+    if !has_column {
+        info!("Adding {} column to history table", column);
+        conn.execute(&format!("ALTER TABLE 'values' ADD COLUMN {} {}", column, definition), ())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_range_query_uses_timestamp_index() {
+        let db = Db::new(":memory:").unwrap();
+        let conn = db.open().unwrap();
+        create_schema(&conn);
+        let plan: Vec<String> = conn
+            .prepare("EXPLAIN QUERY PLAN SELECT * FROM 'values' WHERE timestamp BETWEEN ? AND ? ORDER BY timestamp DESC LIMIT ?")
+            .unwrap()
+            .query_map(rusqlite::params!["2024-01-01 00:00:00", "now", 100], |row| row.get::<_, String>(3))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(plan.iter().any(|step| step.contains("idx_values_timestamp")), "{:?}", plan);
+    }
+
+    #[test]
+    fn reads_while_a_write_is_open() {
+        let dir = crate::testing::temp_dir();
+        let db = Db::new(dir.path().join("history.db").to_str().unwrap()).unwrap();
+        let writer = db.open().unwrap();
+        create_schema(&writer);
+        let journal_mode: String = writer.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "wal");
+        // An uncommitted write holds the write lock, the reader sees the last
+        // commit instead of failing with "database is locked"
+        writer.execute_batch("BEGIN IMMEDIATE").unwrap();
+        writer
+            .execute("INSERT INTO 'values' (cpu_usage, mem_total, mem_used) VALUES (1.0, 1, 1)", [])
+            .unwrap();
+        let reader = db.open().unwrap();
+        let rows: i64 = reader.query_row("SELECT COUNT(*) FROM 'values'", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 0);
+        writer.execute_batch("COMMIT").unwrap();
+        let rows: i64 = reader.query_row("SELECT COUNT(*) FROM 'values'", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 1);
+    }
+}
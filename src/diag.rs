@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use axum::Json;
+use axum::extract::State;
+use log::{error, trace};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+#[utoipa::path(
+    get,
+    path = "/diag",
+    responses(
+        (status = 200, description = "Status and last error of each collector and data source", body = DiagResponse),
+    )
+)]
+pub async fn get_diag(State(state): State<AppState>) -> Json<DiagResponse> {
+    // Probe every collector now and report it alongside its recorded history,
+    // so a sensor that fails only now and then still shows up
+    trace!("Running diagnostics for http request");
+    // Collectors may block, cpu_usage sleeps between its two samples
+    let collectors = tokio::task::spawn_blocking(move || probe(&state))
+        .await
+        .unwrap_or_else(|e| {
+            error!("Diagnostics task failed: {}", e);
+            BTreeMap::new()
+        });
+    Json(DiagResponse { collectors })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DiagResponse {
+    collectors: BTreeMap<&'static str, CollectorDiag>,
+}
+
+// A collector probed now, with the outcomes recorded before
+#[derive(Serialize, ToSchema)]
+pub struct CollectorDiag {
+    supported: bool,
+    ok: bool,
+    error: Option<String>,
+    last_success: Option<String>,
+    last_error: Option<String>,
+    last_error_at: Option<String>,
+}
+
+fn probe(state: &AppState) -> BTreeMap<&'static str, CollectorDiag> {
+    let registry = &state.collectors;
+    let mut collectors = BTreeMap::new();
+    for collector in registry.iter() {
+        let supported = collector.supported();
+        let current = if supported {
+            registry.collect(collector).err()
+        } else {
+            Some("Not supported on this board".to_string())
+        };
+        let status = registry.status(collector.name());
+        collectors.insert(
+            collector.name(),
+            CollectorDiag {
+                supported,
+                ok: current.is_none(),
+                error: current,
+                last_success: status.last_success.map(|t| t.to_rfc3339()),
+                last_error: status.last_error,
+                last_error_at: status.last_error_at.map(|t| t.to_rfc3339()),
+            },
+        );
+    }
+    collectors
+}
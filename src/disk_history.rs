@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use axum::Json;
+use axum::extract::{Query, State};
+use log::{error, trace};
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::db::Db;
+use crate::error::{ApiError, ApiResult};
+
+pub const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS disk_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    mount TEXT NOT NULL,
+    total INTEGER,
+    used INTEGER,
+    free INTEGER,
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP
+    )";
+
+pub fn log_mounts(conn: &Connection, mounts: &[String]) {
+    // One row per configured mount in kilobytes, a mount that can't be read is skipped
+    for mount in mounts {
+        let (total, used, free) = match crate::disk_usage(mount) {
+            Ok(usage) => usage,
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+        trace!("Logging disk usage for {}: Total: {}, Used: {}, Free: {}", mount, total, used, free);
+        if let Err(e) = conn.execute(
+            "INSERT INTO disk_history (mount, total, used, free) VALUES (?, ?, ?, ?)",
+            params![mount, total as i64, used as i64, free as i64],
+        ) {
+            error!("Failed to log disk usage for {}: {}", mount, e);
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/history/disk",
+    params(
+        ("mount" = Option<String>, Query, description = "Mount point, the first of PIDASH_HISTORY_MOUNTS by default"),
+        ("from" = Option<String>, Query, description = "Start of the window, RFC 3339 or YYYY-MM-DD HH:MM:SS UTC, the first row by default as disk history has no default window"),
+        ("to" = Option<String>, Query, description = crate::HISTORY_TO),
+        ("limit" = Option<usize>, Query, description = crate::HISTORY_LIMIT),
+    ),
+    responses(
+        (status = 200, description = "Logged usage of the mount", body = DiskHistoryResponse),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 404, description = "No mounts configured", body = crate::error::ErrorBody),
+        (status = 500, description = "Database query failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_disk_history(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> ApiResult<DiskHistoryResponse> {
+    // History of a single mount, defaulting to the first configured one
+    trace!("Fetching disk history with parameters: {:?}", params);
+    let mount = params
+        .get("mount")
+        .or(state.config.history_mounts.first())
+        .cloned()
+        .ok_or_else(|| ApiError::not_found("No mounts configured, set PIDASH_HISTORY_MOUNTS"))?;
+    let first = "1970-01-01T00:00:00Z".to_string();
+    let last = "now".to_string();
+    let from = crate::history_bound(params.get("from").unwrap_or(&first)).map_err(ApiError::bad_request)?;
+    let to = crate::history_bound(params.get("to").unwrap_or(&last)).map_err(ApiError::bad_request)?;
+    let (limit, clamped) = crate::history_limit(&params, state.config.history_max_limit).map_err(ApiError::bad_request)?;
+    let (db, query_mount) = (state.db.clone(), mount.clone());
+    let data = tokio::task::spawn_blocking(move || query_disk_history(&db, &query_mount, &from, &to, limit))
+        .await
+        .map_err(|e| ApiError::internal(format!("History query task failed: {}", e)))?
+        .map_err(ApiError::internal)?;
+    Ok(Json(DiskHistoryResponse {
+        mount,
+        data,
+        limit_clamped_to: clamped.then_some(limit),
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DiskHistoryResponse {
+    mount: String,
+    data: Vec<DiskHistoryRow>,
+    // As for /history, the limit was lowered to PIDASH_HISTORY_MAX_LIMIT
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit_clamped_to: Option<usize>,
+}
+
+// Sizes in kilobytes
+#[derive(Serialize, ToSchema)]
+pub struct DiskHistoryRow {
+    total: i64,
+    used: i64,
+    free: i64,
+    timestamp: String,
+}
+
+fn query_disk_history(db: &Db, mount: &str, from: &str, to: &str, limit: usize) -> Result<Vec<DiskHistoryRow>, String> {
+    let conn = db.open().map_err(|e| format!("Failed to open database: {}", e))?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT total, used, free, timestamp FROM disk_history
+            WHERE mount = ? AND timestamp BETWEEN ? AND ? ORDER BY timestamp DESC LIMIT ?",
+        )
+        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let rows = stmt
+        .query_map(params![mount, from, to, limit], |row| {
+            Ok(DiskHistoryRow {
+                total: row.get(0)?,
+                used: row.get(1)?,
+                free: row.get(2)?,
+                timestamp: row.get(3)?,
+            })
+        })
+        .map_err(|e| format!("Query execution failed: {}", e))?;
+    rows.collect::<rusqlite::Result<Vec<DiskHistoryRow>>>()
+        .map_err(|e| format!("Error processing row: {}", e))
+}
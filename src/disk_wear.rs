@@ -0,0 +1,163 @@
+use axum::Json;
+use axum::extract::State;
+use log::trace;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::config::Config;
+use crate::error::{ApiError, ApiResult};
+
+// /proc/diskstats counts sectors of 512 bytes regardless of the device's block size
+const SECTOR_SIZE: u64 = 512;
+
+pub const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS disk_writes (
+    device TEXT PRIMARY KEY,
+    boot_id TEXT NOT NULL,
+    last_sectors INTEGER NOT NULL,
+    total_bytes INTEGER NOT NULL
+    )";
+
+pub struct DiskWrites {
+    pub since_boot: u64,
+    pub total: u64,
+}
+
+pub fn update(conn: &Connection, config: &Config) -> Result<DiskWrites, String> {
+    // Persist the running total, only the logger does this so reads stay
+    // read-only
+    let (sectors, boot_id, writes) = current(conn, config)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO disk_writes (device, boot_id, last_sectors, total_bytes) VALUES (?, ?, ?, ?)",
+        params![config.sd_device, boot_id, sectors as i64, writes.total as i64],
+    )
+    .map_err(|e| format!("Failed to store disk write totals: {}", e))?;
+    Ok(writes)
+}
+
+pub fn read(conn: &Connection, config: &Config) -> Result<DiskWrites, String> {
+    // The same totals as update, without storing them
+    current(conn, config).map(|(_, _, writes)| writes)
+}
+
+fn current(conn: &Connection, config: &Config) -> Result<(u64, String, DiskWrites), String> {
+    // Fold the kernel's since-boot counter into the persisted running total
+    let sectors = sectors_written(&config.sd_device)?;
+    let boot_id = boot_id();
+    let stored = conn
+        .query_row(
+            "SELECT boot_id, last_sectors, total_bytes FROM disk_writes WHERE device = ?",
+            params![config.sd_device],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?)),
+        )
+        .optional()
+        .map_err(|e| format!("Failed to read disk write totals: {}", e))?;
+    let total = running_total(stored, &boot_id, sectors);
+    trace!("Disk writes for {} - since boot: {}, total: {}", config.sd_device, sectors * SECTOR_SIZE, total);
+    let writes = DiskWrites {
+        since_boot: sectors * SECTOR_SIZE,
+        total,
+    };
+    Ok((sectors, boot_id, writes))
+}
+
+fn running_total(stored: Option<(String, i64, i64)>, boot_id: &str, sectors: u64) -> u64 {
+    // The kernel counter only resets on reboot, so a service restart within the
+    // same boot must only add the delta since the last stored reading
+    match stored {
+        Some((stored_boot_id, last_sectors, total_bytes)) => {
+            let last_sectors = last_sectors as u64;
+            let total_bytes = total_bytes as u64;
+            if stored_boot_id == boot_id && sectors >= last_sectors {
+                total_bytes + (sectors - last_sectors) * SECTOR_SIZE
+            } else {
+                // Rebooted since the last reading, everything written this boot is new
+                total_bytes + sectors * SECTOR_SIZE
+            }
+        }
+        None => sectors * SECTOR_SIZE,
+    }
+}
+
+fn sectors_written(device: &str) -> Result<u64, String> {
+    let diskstats = std::fs::read_to_string("/proc/diskstats")
+        .map_err(|e| format!("Failed to read /proc/diskstats: {}", e))?;
+    let fields: Vec<&str> = diskstats
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .find(|fields| fields.get(2) == Some(&device))
+        .ok_or_else(|| format!("Device {} not found in /proc/diskstats", device))?;
+    fields
+        .get(9)
+        .and_then(|s| s.parse::<u64>().ok())
+        .ok_or_else(|| format!("Failed to parse sectors written for {}", device))
+}
+
+fn boot_id() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default()
+}
+
+#[utoipa::path(
+    get,
+    path = "/disk_writes",
+    responses(
+        (status = 200, description = "Bytes written to the SD card", body = DiskWritesResponse),
+        (status = 500, description = "Reading failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_disk_writes(State(state): State<AppState>) -> ApiResult<DiskWritesResponse> {
+    // Report the cumulative bytes written to the SD card and an estimated wear figure
+    trace!("Fetching disk write totals for http request");
+    let task_state = state.clone();
+    let writes = tokio::task::spawn_blocking(move || {
+        let conn = task_state.db.open().map_err(|e| format!("Failed to open database: {}", e))?;
+        read(&conn, &task_state.config)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Disk writes task failed: {}", e)))?
+    .map_err(ApiError::internal)?;
+    let config = &state.config;
+    let endurance_bytes = config.card_endurance_tbw * 1e12;
+    let wear_percent = if endurance_bytes > 0.0 {
+        writes.total as f64 / endurance_bytes * 100.0
+    } else {
+        0.0
+    };
+    Ok(Json(DiskWritesResponse {
+        device: config.sd_device.clone(),
+        bytes_written_since_boot: writes.since_boot,
+        bytes_written_total: writes.total,
+        endurance_bytes: endurance_bytes as u64,
+        wear_percent,
+        life_remaining_percent: (100.0 - wear_percent).max(0.0),
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DiskWritesResponse {
+    device: String,
+    bytes_written_since_boot: u64,
+    bytes_written_total: u64,
+    endurance_bytes: u64,
+    wear_percent: f64,
+    life_remaining_percent: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_total_adds_writes_since_last_reading() {
+        assert_eq!(running_total(None, "boot", 10), 10 * SECTOR_SIZE);
+        let stored = || Some(("boot".to_string(), 4, 1000));
+        // Restarted within the same boot, only the delta is new
+        assert_eq!(running_total(stored(), "boot", 10), 1000 + 6 * SECTOR_SIZE);
+        // Rebooted, the whole counter is new
+        assert_eq!(running_total(stored(), "other", 10), 1000 + 10 * SECTOR_SIZE);
+        assert_eq!(running_total(stored(), "boot", 2), 1000 + 2 * SECTOR_SIZE);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use axum::Json;
+use axum::extract::State;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use http::HeaderMap;
+use log::{error, info, trace};
+use rusqlite::Connection;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::db::Db;
+use crate::error::{ApiError, ApiResult};
+use crate::{AppState, HISTORY_COLUMNS, auth, history_row};
+
+#[derive(Serialize, ToSchema)]
+pub struct Export {
+    path: String,
+    size: u64,
+    rows: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/history/export-file",
+    responses(
+        (status = 200, description = "Path, size and row count of the written file", body = Export),
+        (status = 401, description = "Invalid or missing bearer token", body = crate::error::ErrorBody),
+        (status = 403, description = "PIDASH_API_TOKEN is not set", body = crate::error::ErrorBody),
+        (status = 500, description = "Writing the export failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn export_history_file(State(state): State<AppState>, headers: HeaderMap) -> ApiResult<Export> {
+    // Write the full history to a gzip compressed JSON lines file on the Pi
+    trace!("Handling history file export request");
+    auth::check_token(&headers, &state.config).inspect_err(|e| error!("Rejected history export: {}", e.message()))?;
+    let dir = PathBuf::from(&state.config.export_dir);
+    let db = state.db.clone();
+    let export = tokio::task::spawn_blocking(move || write_export(&db, &dir))
+        .await
+        .map_err(|e| ApiError::internal(format!("History export task failed: {}", e)))?
+        .map_err(ApiError::internal)?;
+    info!("Exported {} history rows to {}", export.rows, export.path);
+    Ok(Json(export))
+}
+
+fn write_export(db: &Db, dir: &Path) -> Result<Export, String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create export directory {}: {}", dir.display(), e))?;
+    let conn = db.open().map_err(|e| format!("Failed to open database: {}", e))?;
+    // Written under a .partial name and renamed once complete, so the export
+    // directory never holds a truncated export
+    let (partial, file) = create_export(dir, "jsonl.gz.partial")?;
+    let written = write_rows(&conn, file).and_then(|count| {
+        // Reserves the final name, the rename then replaces the empty file
+        let (path, _) = create_export(dir, "jsonl.gz")?;
+        std::fs::rename(&partial, &path).map_err(|e| {
+            let _ = std::fs::remove_file(&path);
+            format!("Failed to move export to {}: {}", path.display(), e)
+        })?;
+        Ok((path, count))
+    });
+    let (path, count) = written.inspect_err(|_| {
+        if let Err(e) = std::fs::remove_file(&partial) {
+            error!("Failed to remove incomplete export {}: {}", partial.display(), e);
+        }
+    })?;
+    let size = std::fs::metadata(&path)
+        .map(|m| m.len())
+        .map_err(|e| format!("Failed to read export size: {}", e))?;
+    Ok(Export {
+        path: path.display().to_string(),
+        size,
+        rows: count,
+    })
+}
+
+fn write_rows(conn: &Connection, file: File) -> Result<usize, String> {
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM 'values' ORDER BY timestamp ASC", HISTORY_COLUMNS))
+        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let rows = stmt
+        .query_map([], history_row)
+        .map_err(|e| format!("Query execution failed: {}", e))?;
+
+    // Compress row by row so the dataset never has to fit in memory
+    let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+    let mut count = 0;
+    for row in rows {
+        let row = row.map_err(|e| format!("Error processing row: {}", e))?;
+        serde_json::to_writer(&mut encoder, &row)
+            .map_err(|e| e.to_string())
+            .and_then(|()| writeln!(encoder).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to write export: {}", e))?;
+        count += 1;
+    }
+    encoder
+        .finish()
+        .and_then(|mut writer| writer.flush())
+        .map_err(|e| format!("Failed to write export: {}", e))?;
+    Ok(count)
+}
+
+fn create_export(dir: &Path, extension: &str) -> Result<(PathBuf, File), String> {
+    // Exports within the same second get a counter instead of overwriting
+    // each other
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+    let mut n = 0;
+    loop {
+        let name = match n {
+            0 => format!("history-{}.{}", stamp, extension),
+            n => format!("history-{}-{}.{}", stamp, n, extension),
+        };
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(format!("Failed to create {}: {}", path.display(), e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(dir: &Path) -> Vec<String> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn export_is_renamed_once_complete() {
+        let dir = crate::testing::temp_dir();
+        let db = Db::new(":memory:").unwrap();
+        crate::db::create_schema(&db.open().unwrap());
+        let export = write_export(&db, dir.path()).unwrap();
+        assert_eq!(export.rows, 0);
+        let files = files(dir.path());
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with(".jsonl.gz"));
+    }
+
+    #[test]
+    fn failed_export_leaves_no_file() {
+        let dir = crate::testing::temp_dir();
+        // No schema, reading the history fails after the file was created
+        let db = Db::new(":memory:").unwrap();
+        assert!(write_export(&db, dir.path()).is_err());
+        assert!(files(dir.path()).is_empty());
+    }
+}
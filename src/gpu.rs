@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::Json;
+use axum::extract::{Query, State};
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::command::{self, CommandError};
+use crate::error::{ApiError, ApiResult};
+use crate::thermal::TempUnit;
+
+#[utoipa::path(
+    get,
+    path = "/gpu",
+    params(
+        ("unit" = Option<String>, Query, description = "c or f, Celsius by default"),
+    ),
+    responses(
+        (status = 200, description = "GPU memory split and temperature", body = GpuResponse),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_gpu(State(state): State<AppState>, Query(params): Query<HashMap<String, String>>) -> ApiResult<GpuResponse> {
+    // Memory split between the ARM cores and the VideoCore GPU, and the GPU
+    // temperature. A reading that fails, e.g. without vcgencmd on a non-Pi host,
+    // is null with its error under `errors`.
+    trace!("Fetching GPU memory split for http request");
+    let unit = TempUnit::from_params(&params).map_err(ApiError::bad_request)?;
+    let reading = read_gpu(&state).await;
+    Ok(Json(GpuResponse {
+        temp: reading.temp_celsius.map(|temp| unit.convert(temp)),
+        unit: unit.symbol(),
+        reading,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GpuResponse {
+    #[serde(flatten)]
+    reading: GpuReading,
+    // temp_celsius in the unit asked for
+    temp: Option<f64>,
+    unit: &'static str,
+}
+
+// Sizes in megabytes, a reading that failed is null with its error in `errors`
+#[derive(Serialize, Deserialize, ToSchema)]
+struct GpuReading {
+    gpu_mem_mb: Option<f64>,
+    arm_mem_mb: Option<f64>,
+    total_mem_mb: Option<f64>,
+    gpu_mem_percent: Option<f64>,
+    temp_celsius: Option<f64>,
+    errors: BTreeMap<String, String>,
+}
+
+async fn read_gpu(state: &AppState) -> GpuReading {
+    if let Some(cached) = state.metric_cache.get("gpu").and_then(|cached| serde_json::from_value(cached).ok()) {
+        return cached;
+    }
+    let (gpu, arm, temp) = tokio::join!(
+        command::vcgencmd(&state.config, &["get_mem", "gpu"]),
+        command::vcgencmd(&state.config, &["get_mem", "arm"]),
+        command::vcgencmd(&state.config, &["measure_temp"]),
+    );
+    let mut errors = BTreeMap::new();
+    let mut read = |name: &str, result: Result<String, CommandError>, parse: fn(&str) -> Option<f64>| {
+        let value = result
+            .map_err(|e| e.to_string())
+            .and_then(|stdout| parse(&stdout).ok_or_else(|| format!("Failed to parse vcgencmd output: {}", stdout.trim())));
+        value
+            .inspect_err(|e| {
+                error!("Failed to read {}: {}", name, e);
+                errors.insert(name.to_string(), e.clone());
+            })
+            .ok()
+    };
+    let gpu_mb = read("gpu_mem_mb", gpu, |stdout| parse_mem_mb(stdout, "gpu"));
+    let arm_mb = read("arm_mem_mb", arm, |stdout| parse_mem_mb(stdout, "arm"));
+    let temp = read("temp_celsius", temp, parse_temp);
+    let total_mb = gpu_mb.zip(arm_mb).map(|(gpu, arm)| gpu + arm);
+    // Partial readings are not cached, so a failed call is retried on the next request
+    let reading = GpuReading {
+        gpu_mem_mb: gpu_mb,
+        arm_mem_mb: arm_mb,
+        total_mem_mb: total_mb,
+        gpu_mem_percent: gpu_mb.zip(total_mb).map(|(gpu, total)| gpu / total * 100.0),
+        temp_celsius: temp,
+        errors,
+    };
+    if reading.errors.is_empty()
+        && let Ok(value) = serde_json::to_value(&reading)
+    {
+        state.metric_cache.insert("gpu".to_string(), value);
+    }
+    reading
+}
+
+fn parse_mem_mb(stdout: &str, key: &str) -> Option<f64> {
+    // "gpu=76M", larger splits can be given in G
+    let value = command::vcgencmd_field(stdout, key)?;
+    if let Some(gb) = value.strip_suffix('G') {
+        return gb.parse::<f64>().ok().map(|gb| gb * 1024.0);
+    }
+    value.strip_suffix('M')?.parse::<f64>().ok()
+}
+
+fn parse_temp(stdout: &str) -> Option<f64> {
+    // "temp=47.2'C"
+    command::vcgencmd_field(stdout, "temp")?.strip_suffix("'C")?.parse::<f64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_vcgencmd_output() {
+        assert_eq!(parse_mem_mb("gpu=76M\n", "gpu"), Some(76.0));
+        assert_eq!(parse_mem_mb("arm=1G\n", "arm"), Some(1024.0));
+        assert_eq!(parse_mem_mb("arm=948M\n", "gpu"), None);
+        assert_eq!(parse_temp("temp=47.2'C\n"), Some(47.2));
+        assert_eq!(parse_temp("error=1 error_msg=\"Command not registered\""), None);
+    }
+}
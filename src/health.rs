@@ -0,0 +1,98 @@
+use axum::Json;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use http::StatusCode;
+use std::collections::BTreeMap;
+
+use log::{error, trace};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+// Files every request path depends on, checked by /ready
+const REQUIRED_FILES: [&str; 2] = ["/proc/stat", "/proc/meminfo"];
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "The server is up", body = HealthResponse),
+    )
+)]
+pub async fn get_health() -> Json<HealthResponse> {
+    // Liveness only, answers as long as the server is serving requests
+    Json(HealthResponse { status: "ok" })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HealthResponse {
+    status: &'static str,
+}
+
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "The database and required files are available", body = ReadyResponse),
+        (status = 503, description = "A readiness check failed", body = ReadyResponse),
+    )
+)]
+pub async fn get_ready(State(state): State<AppState>) -> impl IntoResponse {
+    // Readiness: the history database answers a query and the required /proc
+    // files can be read. 503 with the failed checks otherwise.
+    trace!("Checking readiness for http request");
+    let checks = tokio::task::spawn_blocking(move || run_checks(&state))
+        .await
+        .unwrap_or_else(|e| BTreeMap::from([("task".to_string(), check(Err(e.to_string())))]));
+    let ready = checks.values().all(|check| check.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        error!("Not ready: {:?}", checks);
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(ReadyResponse {
+            status: if ready { "ready" } else { "unavailable" },
+            checks,
+        }),
+    )
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadyResponse {
+    // ready or unavailable
+    status: &'static str,
+    checks: BTreeMap<String, Check>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn run_checks(state: &AppState) -> BTreeMap<String, Check> {
+    let mut checks = BTreeMap::new();
+    let database = state
+        .db
+        .open()
+        .map_err(|e| e.to_string())
+        .and_then(|conn| conn.query_row("SELECT 1", [], |_| Ok(())).map_err(|e| e.to_string()));
+    checks.insert("database".to_string(), check(database));
+    for path in REQUIRED_FILES {
+        let readable = std::fs::File::open(path).map(|_| ()).map_err(|e| e.to_string());
+        checks.insert(path.to_string(), check(readable));
+    }
+    checks
+}
+
+fn check(result: Result<(), String>) -> Check {
+    Check {
+        ok: result.is_ok(),
+        error: result.err(),
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Response};
+use futures_util::stream;
+use http::header;
+use log::{error, trace};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::AppState;
+use crate::error::ApiError;
+
+// Columns of a history row and of an averaged bucket, in CSV order
+const ROW_COLUMNS: [&str; 10] = [
+    "timestamp",
+    "cpu_usage",
+    "mem_total",
+    "mem_used",
+    "disk_total",
+    "disk_used",
+    "disk_free",
+    "cpu_temp",
+    "fan_speed",
+    "anomaly",
+];
+const BUCKET_COLUMNS: [&str; 5] = ["timestamp", "cpu_usage", "mem_used", "disk_used", "count"];
+// The /history parameters the CSV honours. Timestamps stay in the stored UTC
+// format and network counters don't fit a row, so tz and include_network
+// are ignored.
+const PARAMS: [&str; 4] = ["from", "to", "limit", "bucket"];
+// Lines buffered between the database thread and the response
+const LINE_BUFFER: usize = 256;
+// A client that reads nothing for this long is dropped, so a stalled download
+// doesn't keep one of the few pooled connections
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[utoipa::path(
+    get,
+    path = "/history.csv",
+    params(
+        ("from" = Option<String>, Query, description = crate::HISTORY_FROM),
+        ("to" = Option<String>, Query, description = crate::HISTORY_TO),
+        ("limit" = Option<usize>, Query, description = crate::HISTORY_LIMIT),
+        ("bucket" = Option<String>, Query, description = "Average over buckets such as 5m or 1h"),
+    ),
+    responses(
+        (status = 200, description = "History rows or bucket averages as CSV, with UTC timestamps as stored", content_type = "text/csv", body = String),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 500, description = "Database query failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_history_csv(
+    State(state): State<AppState>,
+    Query(mut params): Query<HashMap<String, String>>,
+) -> Result<Response, ApiError> {
+    // The rows /history returns, as a CSV download. Rows are written to the
+    // response as they are read rather than collected first.
+    params.retain(|name, _| PARAMS.contains(&name.as_str()));
+    trace!("Exporting history as CSV with parameters: {:?}", params);
+    let query = crate::history_query(&state.config, &params)?;
+    let db = state.db.clone();
+    let conn = tokio::task::spawn_blocking(move || db.open())
+        .await
+        .map_err(|e| ApiError::internal(format!("History query task failed: {}", e)))?
+        .map_err(|e| ApiError::internal(format!("Failed to open database: {}", e)))?;
+    let (tx, rx) = mpsc::channel::<String>(LINE_BUFFER);
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let send = |line: String| runtime.block_on(tx.send_timeout(line, SEND_TIMEOUT)).is_ok();
+        // The header is already sent, so a failure can only cut the file short
+        let result = match query.bucket {
+            Some(secs) => crate::history_buckets(&conn, &query.from, &query.to, query.limit, secs).map(|buckets| {
+                // The buckets are computed, the connection isn't needed while sending
+                drop(conn);
+                if !send(csv_line(BUCKET_COLUMNS.iter().map(|c| Value::from(*c)))) {
+                    return;
+                }
+                for bucket in buckets {
+                    if !send(csv_row(&BUCKET_COLUMNS, &bucket)) {
+                        break;
+                    }
+                }
+            }),
+            None => {
+                // Stop reading once the client has gone away or stalled
+                if send(csv_line(ROW_COLUMNS.iter().map(|c| Value::from(*c)))) {
+                    crate::each_history_row(&conn, &query, |row| send(csv_row(&ROW_COLUMNS, &row)))
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        if let Err(e) = result {
+            error!("History CSV export stopped: {}", e);
+        }
+    });
+    let lines = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (Ok::<_, Infallible>(line), rx))
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"pidash-history.csv\""),
+        ],
+        Body::from_stream(lines),
+    )
+        .into_response())
+}
+
+fn csv_row(columns: &[&str], row: &impl Serialize) -> String {
+    // Fields are looked up by column name, in the order of the header
+    let row = serde_json::to_value(row).unwrap_or_default();
+    csv_line(columns.iter().map(|column| row[*column].clone()))
+}
+
+fn csv_line(fields: impl Iterator<Item = Value>) -> String {
+    // Missing values are empty fields, strings are quoted when they need it
+    let fields: Vec<String> = fields
+        .map(|field| match field {
+            Value::Null => String::new(),
+            Value::String(s) if s.contains([',', '"', '\n']) => format!("\"{}\"", s.replace('"', "\"\"")),
+            Value::String(s) => s,
+            other => other.to_string(),
+        })
+        .collect();
+    format!("{}\n", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use utoipa::OpenApi;
+
+    #[test]
+    fn formats_csv_rows() {
+        let row = json!({
+            "timestamp": "2024-01-01 00:00:00",
+            "cpu_usage": 12.5,
+            "mem_total": 3884096,
+            "mem_used": 1000,
+            "disk_total": null,
+            "anomaly": false
+        });
+        assert_eq!(
+            csv_row(&ROW_COLUMNS, &row),
+            "2024-01-01 00:00:00,12.5,3884096,1000,,,,,,false\n"
+        );
+        assert_eq!(csv_line([json!("a,b"), json!("say \"hi\"")].into_iter()), "\"a,b\",\"say \"\"hi\"\"\"\n");
+    }
+
+    #[test]
+    fn spec_lists_the_parameters_read() {
+        let spec = serde_json::to_value(crate::openapi::ApiDoc::openapi()).unwrap();
+        let names: Vec<&str> = spec["paths"]["/history.csv"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| param["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, PARAMS);
+    }
+}
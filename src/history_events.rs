@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use axum::Json;
+use axum::extract::{Query, State};
+use log::trace;
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::error::{ApiError, ApiResult};
+
+// Where the Pi firmware starts soft throttling, in degrees Celsius
+const DEFAULT_THRESHOLD: f64 = 80.0;
+
+// A run of consecutive history rows above the threshold
+#[derive(Serialize, ToSchema)]
+pub struct Event {
+    start: String,
+    end: String,
+    // Highest temperature of the run in degrees Celsius, and when it was logged
+    peak: f64,
+    peak_at: String,
+    samples: usize,
+    // The newest row of the window is still above the threshold
+    ongoing: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/history/events",
+    params(
+        ("from" = Option<String>, Query, description = crate::HISTORY_FROM),
+        ("to" = Option<String>, Query, description = crate::HISTORY_TO),
+        ("threshold" = Option<f64>, Query, description = "CPU temperature in degrees Celsius an event starts above, 80 by default"),
+        ("tz" = Option<String>, Query, description = "IANA timezone for the returned timestamps, PIDASH_TZ by default"),
+    ),
+    responses(
+        (status = 200, description = "Periods the CPU temperature stayed above the threshold, newest first", body = EventsResponse),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 500, description = "Database query failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_history_events(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> ApiResult<EventsResponse> {
+    // Turn the logged temperatures into overheating events with their start,
+    // end and peak. Throttling isn't logged, so only temperature is scanned.
+    // Only the window and timezone of the /history parameters apply here.
+    trace!("Finding history events with parameters: {:?}", params);
+    let (from, to) = crate::history_window(&state.config, &params)?;
+    let timezone = crate::history_timezone(&state.config, &params)?;
+    let threshold = match params.get("threshold") {
+        Some(threshold) => threshold
+            .parse::<f64>()
+            .ok()
+            .filter(|threshold| threshold.is_finite())
+            .ok_or_else(|| ApiError::bad_request(format!("Invalid threshold {:?}, expected degrees Celsius", threshold)))?,
+        None => DEFAULT_THRESHOLD,
+    };
+    let db = state.db.clone();
+    let mut events = tokio::task::spawn_blocking(move || {
+        let conn = db.open().map_err(|e| format!("Failed to open database: {}", e))?;
+        find_events(&conn, &from, &to, threshold)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("History query task failed: {}", e)))?
+    .map_err(ApiError::internal)?;
+    for event in &mut events {
+        for timestamp in [&mut event.start, &mut event.end, &mut event.peak_at] {
+            if let Some(local) = crate::localize_timestamp(timestamp, timezone) {
+                *timestamp = local;
+            }
+        }
+    }
+    events.reverse();
+    Ok(Json(EventsResponse {
+        threshold,
+        unit: "C",
+        timezone: timezone.name(),
+        events,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EventsResponse {
+    threshold: f64,
+    unit: &'static str,
+    timezone: &'static str,
+    events: Vec<Event>,
+}
+
+fn find_events(conn: &Connection, from: &str, to: &str, threshold: f64) -> Result<Vec<Event>, String> {
+    // Oldest first, a row without a temperature ends the current event
+    let mut stmt = conn
+        .prepare("SELECT timestamp, cpu_temp FROM 'values' WHERE timestamp BETWEEN ? AND ? ORDER BY timestamp ASC")
+        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let rows = stmt
+        .query_map(params![from, to], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<i32>>(1)?)))
+        .map_err(|e| format!("Query execution failed: {}", e))?;
+    let mut events = Vec::new();
+    let mut current: Option<Event> = None;
+    for row in rows {
+        let (timestamp, temp) = row.map_err(|e| format!("Error processing row: {}", e))?;
+        // Stored in millidegrees
+        let temp = temp.map(|temp| temp as f64 / 1000.0).filter(|temp| *temp > threshold);
+        match (temp, current.as_mut()) {
+            (Some(temp), Some(event)) => {
+                if temp > event.peak {
+                    event.peak = temp;
+                    event.peak_at = timestamp.clone();
+                }
+                event.end = timestamp;
+                event.samples += 1;
+            }
+            (Some(temp), None) => {
+                current = Some(Event {
+                    start: timestamp.clone(),
+                    end: timestamp.clone(),
+                    peak: temp,
+                    peak_at: timestamp,
+                    samples: 1,
+                    ongoing: false,
+                })
+            }
+            (None, _) => events.extend(current.take()),
+        }
+    }
+    if let Some(mut event) = current {
+        event.ongoing = true;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Db, create_schema};
+
+    #[test]
+    fn finds_runs_above_threshold() {
+        let db = Db::new(":memory:").unwrap();
+        let conn = db.open().unwrap();
+        create_schema(&conn);
+        for (cpu_temp, timestamp) in [
+            (Some(70000), "2024-01-01 14:00:00"),
+            (Some(81000), "2024-01-01 14:01:00"),
+            (Some(84500), "2024-01-01 14:02:00"),
+            (Some(82000), "2024-01-01 14:03:00"),
+            (Some(75000), "2024-01-01 14:04:00"),
+            (Some(83000), "2024-01-01 14:05:00"),
+            (None, "2024-01-01 14:06:00"),
+            (Some(85000), "2024-01-01 14:07:00"),
+        ] {
+            conn.execute(
+                "INSERT INTO 'values' (cpu_usage, mem_total, mem_used, cpu_temp, timestamp) VALUES (1.0, 1, 1, ?, ?)",
+                params![cpu_temp, timestamp],
+            )
+            .unwrap();
+        }
+        let events = find_events(&conn, "2024-01-01 00:00:00", "2024-01-01 23:59:59", 80.0).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!((events[0].start.as_str(), events[0].end.as_str()), ("2024-01-01 14:01:00", "2024-01-01 14:03:00"));
+        assert_eq!((events[0].peak, events[0].peak_at.as_str()), (84.5, "2024-01-01 14:02:00"));
+        assert_eq!(events[0].samples, 3);
+        assert!(!events[1].ongoing);
+        assert!(events[2].ongoing);
+        assert!(find_events(&conn, "2024-01-01 00:00:00", "2024-01-01 23:59:59", 90.0).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::Json;
+use axum::extract::{Query, State};
+use log::trace;
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::error::{ApiError, ApiResult};
+
+// History columns summarized, in the units history stores them in
+const SUMMARY_COLUMNS: [&str; 4] = ["cpu_usage", "cpu_temp", "mem_used", "disk_used"];
+
+#[utoipa::path(
+    get,
+    path = "/history/summary",
+    params(
+        ("from" = Option<String>, Query, description = crate::HISTORY_FROM),
+        ("to" = Option<String>, Query, description = crate::HISTORY_TO),
+    ),
+    responses(
+        (status = 200, description = "Minimum, maximum and average of each column over the window", body = SummaryResponse),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 500, description = "Database query failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_history_summary(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> ApiResult<SummaryResponse> {
+    // Minimum, maximum and average of each summarized column over the from/to
+    // window, with the time of the maximum, without sending the rows
+    trace!("Summarizing history with parameters: {:?}", params);
+    let query = crate::history_query(&state.config, &params)?;
+    let db = state.db.clone();
+    let (from, to) = (query.from.clone(), query.to.clone());
+    let summary = tokio::task::spawn_blocking(move || {
+        let conn = db.open().map_err(|e| format!("Failed to open database: {}", e))?;
+        summarize(&conn, &from, &to)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("History query task failed: {}", e)))?
+    .map_err(ApiError::internal)?;
+    Ok(Json(SummaryResponse {
+        from: query.from,
+        to: query.to,
+        summary,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SummaryResponse {
+    from: String,
+    to: String,
+    summary: Summary,
+}
+
+// Rows in the window, and the statistics of each of SUMMARY_COLUMNS by name
+#[derive(Serialize, ToSchema)]
+pub struct Summary {
+    rows: i64,
+    #[serde(flatten)]
+    columns: BTreeMap<&'static str, ColumnSummary>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ColumnSummary {
+    min: Option<f64>,
+    max: Option<f64>,
+    avg: Option<f64>,
+    // Rows with a value in the column
+    count: i64,
+    max_at: Option<String>,
+}
+
+fn summarize(conn: &Connection, from: &str, to: &str) -> Result<Summary, String> {
+    let rows: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM 'values' WHERE timestamp BETWEEN ? AND ?",
+            params![from, to],
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("Query execution failed: {}", e))?;
+    let mut columns = BTreeMap::new();
+    for column in SUMMARY_COLUMNS {
+        // The latest row holding the maximum gives its time
+        let sql = format!(
+            "SELECT MIN({0}), MAX({0}), AVG({0}), COUNT({0}),
+            (SELECT timestamp FROM 'values' WHERE timestamp BETWEEN ?1 AND ?2 AND {0} IS NOT NULL
+                ORDER BY {0} DESC, timestamp DESC LIMIT 1)
+            FROM 'values' WHERE timestamp BETWEEN ?1 AND ?2",
+            column
+        );
+        let stats = conn
+            .query_row(&sql, params![from, to], |row| {
+                Ok(ColumnSummary {
+                    min: row.get(0)?,
+                    max: row.get(1)?,
+                    avg: row.get(2)?,
+                    count: row.get(3)?,
+                    max_at: row.get(4)?,
+                })
+            })
+            .map_err(|e| format!("Failed to summarize {}: {}", column, e))?;
+        columns.insert(column, stats);
+    }
+    Ok(Summary { rows, columns })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Db, create_schema};
+    use serde_json::Value;
+
+    #[test]
+    fn summarizes_window() {
+        let db = Db::new(":memory:").unwrap();
+        let conn = db.open().unwrap();
+        create_schema(&conn);
+        for (cpu_usage, cpu_temp, timestamp) in [
+            (10.0, Some(40000), "2024-01-01 00:00:00"),
+            (30.0, Some(55000), "2024-01-01 00:01:00"),
+            (20.0, None, "2024-01-01 00:02:00"),
+            (90.0, Some(80000), "2024-01-02 00:00:00"),
+        ] {
+            conn.execute(
+                "INSERT INTO 'values' (cpu_usage, mem_total, mem_used, cpu_temp, timestamp) VALUES (?, 1, 1, ?, ?)",
+                params![cpu_usage, cpu_temp, timestamp],
+            )
+            .unwrap();
+        }
+        let summary = serde_json::to_value(summarize(&conn, "2024-01-01 00:00:00", "2024-01-01 23:59:59").unwrap()).unwrap();
+        assert_eq!(summary["rows"], 3);
+        assert_eq!(summary["cpu_usage"]["min"], 10.0);
+        assert_eq!(summary["cpu_usage"]["avg"], 20.0);
+        assert_eq!(summary["cpu_usage"]["max_at"], "2024-01-01 00:01:00");
+        assert_eq!(summary["cpu_temp"]["count"], 2);
+        assert_eq!(summary["cpu_temp"]["max"], 55000.0);
+        assert_eq!(summary["disk_used"]["max"], Value::Null);
+    }
+}
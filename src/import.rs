@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use axum::Json;
+use axum::extract::State;
+use http::HeaderMap;
+use log::{error, info, trace};
+use rusqlite::params;
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::db::Db;
+use crate::error::{ApiError, ApiResult};
+use crate::{AppState, auth};
+
+// Size fields of a history row. They are stored in kilobytes (1024 bytes), the
+// unit /proc/meminfo and statvfs based disk figures are read in.
+const SIZE_FIELDS: [&str; 5] = ["mem_total", "mem_used", "disk_total", "disk_used", "disk_free"];
+
+fn unit_bytes(unit: &str) -> Option<f64> {
+    match unit {
+        "bytes" | "B" => Some(1.0),
+        "kB" | "KiB" => Some(1024.0),
+        "MB" | "MiB" => Some(1024.0 * 1024.0),
+        "GB" | "GiB" => Some(1024.0 * 1024.0 * 1024.0),
+        _ => None,
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/history/import",
+    request_body = serde_json::Value,
+    responses(
+        (status = 200, description = "Number of rows imported", body = ImportResponse),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 401, description = "Invalid or missing bearer token", body = crate::error::ErrorBody),
+        (status = 403, description = "PIDASH_API_TOKEN is not set", body = crate::error::ErrorBody),
+        (status = 500, description = "Database query failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn import_history(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut body): Json<Value>,
+) -> ApiResult<ImportResponse> {
+    // Insert history rows recorded elsewhere, converting their size fields from
+    // the unit given per field in `units` to kilobytes
+    trace!("Handling history import request");
+    auth::check_token(&headers, &state.config).inspect_err(|e| error!("Rejected history import: {}", e.message()))?;
+    let scales = parse_units(&body["units"]).map_err(ApiError::bad_request)?;
+    let Value::Array(rows) = body["rows"].take() else {
+        return Err(ApiError::bad_request("Expected a rows array"));
+    };
+    let db = state.db.clone();
+    let count = tokio::task::spawn_blocking(move || insert_rows(&db, &rows, &scales))
+        .await
+        .map_err(|e| ApiError::internal(format!("History import task failed: {}", e)))??;
+    info!("Imported {} history rows", count);
+    // Imported rows may hold new peak temperatures
+    state.temp_peak.lock().unwrap().invalidate();
+    Ok(Json(ImportResponse { rows: count }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportResponse {
+    rows: usize,
+}
+
+fn parse_units(units: &Value) -> Result<HashMap<&'static str, f64>, String> {
+    // Fields without a unit are taken to already be in kilobytes
+    let mut scales = HashMap::new();
+    for field in SIZE_FIELDS {
+        let scale = match units.get(field) {
+            Some(unit) => {
+                let unit = unit.as_str().ok_or_else(|| format!("Unit for {} must be a string", field))?;
+                unit_bytes(unit).ok_or_else(|| format!("Unknown unit for {}: {}", field, unit))? / 1024.0
+            }
+            None => 1.0,
+        };
+        scales.insert(field, scale);
+    }
+    Ok(scales)
+}
+
+fn insert_rows(db: &Db, rows: &[Value], scales: &HashMap<&'static str, f64>) -> Result<usize, ApiError> {
+    let mut conn = db.open().map_err(|e| ApiError::internal(format!("Failed to open database: {}", e)))?;
+    // All or nothing, so a bad row doesn't leave half an import behind
+    let tx = conn
+        .transaction()
+        .map_err(|e| ApiError::internal(format!("Failed to start transaction: {}", e)))?;
+    for (i, row) in rows.iter().enumerate() {
+        let size = |field: &'static str| {
+            row[field].as_f64().map(|v| (v * scales[field]).round() as i64)
+        };
+        // A row missing a required field is the client's mistake
+        let missing = |field: &str| ApiError::bad_request(format!("Row {} has no {}", i, field));
+        // Stored in UTC without a zone, /history returns RFC 3339 times
+        let timestamp = row["timestamp"]
+            .as_str()
+            .ok_or_else(|| missing("timestamp"))?;
+        let timestamp = crate::history_bound(timestamp)
+            .map_err(|e| ApiError::bad_request(format!("Row {}: {}", i, e)))?;
+        // Disk figures may be missing, the rest are required by /history
+        let cpu_usage = row["cpu_usage"].as_f64().ok_or_else(|| missing("cpu_usage"))?;
+        let mem_total = size("mem_total").ok_or_else(|| missing("mem_total"))?;
+        let mem_used = size("mem_used").ok_or_else(|| missing("mem_used"))?;
+        tx.execute(
+            "INSERT INTO 'values' (cpu_usage, mem_total, mem_used, disk_total, disk_used, disk_free, timestamp, anomaly, cpu_temp, fan_speed) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                cpu_usage,
+                mem_total,
+                mem_used,
+                size("disk_total"),
+                size("disk_used"),
+                size("disk_free"),
+                timestamp,
+                row["anomaly"].as_bool().unwrap_or(false),
+                row["cpu_temp"].as_i64(),
+                row["fan_speed"].as_i64()
+            ],
+        )
+        .map_err(|e| ApiError::internal(format!("Failed to insert row {}: {}", i, e)))?;
+    }
+    tx.commit()
+        .map_err(|e| ApiError::internal(format!("Failed to commit import: {}", e)))?;
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn converts_size_fields_to_kilobytes() {
+        let db = Db::new(":memory:").unwrap();
+        crate::db::create_schema(&db.open().unwrap());
+        let scales = parse_units(&json!({"mem_total": "bytes", "mem_used": "bytes", "disk_total": "MB"})).unwrap();
+        let rows = [json!({
+            "cpu_usage": 12.5,
+            "mem_total": 4096.0 * 1024.0,
+            "mem_used": 2048.0 * 1024.0,
+            "disk_total": 10.0,
+            "disk_used": 5120,
+            "timestamp": "2024-01-01 00:00:00"
+        })];
+        assert_eq!(insert_rows(&db, &rows, &scales).unwrap(), 1);
+        let stored = db
+            .open()
+            .unwrap()
+            .query_row("SELECT mem_total, mem_used, disk_total, disk_used, disk_free FROM 'values'", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+            })
+            .unwrap();
+        assert_eq!(stored, (4096, 2048, Some(10240), Some(5120), None::<i64>));
+    }
+
+    #[test]
+    fn rejects_unknown_units() {
+        assert!(parse_units(&json!({"mem_used": "furlongs"})).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use log::{error, info, trace};
+use serde_json::Value;
+
+use crate::AppState;
+use crate::config::Config;
+use crate::metrics;
+
+// Time a push may take, so an unreachable server can't hold up the logger
+const PUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Pushes each logged sample to an InfluxDB write endpoint
+pub struct Pusher {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl Pusher {
+    // None unless both the write URL and the token are configured
+    pub fn new(config: &Config) -> Option<Pusher> {
+        let url = config.influx_url.clone()?;
+        let Some(token) = config.influx_token.clone() else {
+            error!("PIDASH_INFLUX_URL is set without PIDASH_INFLUX_TOKEN, not pushing to InfluxDB");
+            return None;
+        };
+        let client = reqwest::Client::builder()
+            .timeout(PUSH_TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client");
+        info!("Pushing history samples to InfluxDB at {}", url);
+        Some(Pusher { client, url, token })
+    }
+
+    // Write the sample as line protocol. Failures are logged and the sample
+    // dropped, the next cycle pushes a fresh one.
+    pub async fn push(&self, state: &AppState, sample: &HashMap<&'static str, Value>) {
+        let tags = metrics::influx_tags(state).await;
+        let timestamp = chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0);
+        // Keep the registry order so points are written in a stable order
+        let collected = state
+            .collectors
+            .iter()
+            .filter_map(|collector| Some((collector.name(), metrics::numeric_fields(sample.get(collector.name())?.clone()))));
+        let body = metrics::influx_lines(&tags, collected, timestamp);
+        if body.is_empty() {
+            return;
+        }
+        let res = self
+            .client
+            .post(&self.url)
+            .header("Authorization", format!("Token {}", self.token))
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        match res {
+            Ok(_) => trace!("Pushed sample to InfluxDB"),
+            Err(e) => error!("Failed to push sample to InfluxDB: {}", e),
+        }
+    }
+}
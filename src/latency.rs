@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::extract::{FromRequestParts, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_client_ip::ClientIp;
+use log::warn;
+
+use crate::AppState;
+
+// Number of recent requests the percentiles are computed over
+const WINDOW: usize = 1024;
+
+#[derive(Default)]
+struct Samples {
+    count: u64,
+    sum: Duration,
+    recent: VecDeque<Duration>,
+}
+
+// Handler durations of served requests, for /metrics
+#[derive(Default)]
+pub struct Latency {
+    samples: Mutex<Samples>,
+}
+
+// Request count and total time since startup, and the median and 95th
+// percentile of the last WINDOW requests
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    pub count: u64,
+    pub sum: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+}
+
+impl Latency {
+    pub fn record(&self, duration: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        samples.count += 1;
+        samples.sum += duration;
+        if samples.recent.len() == WINDOW {
+            samples.recent.pop_front();
+        }
+        samples.recent.push_back(duration);
+    }
+
+    pub fn stats(&self) -> Stats {
+        let samples = self.samples.lock().unwrap();
+        let mut recent: Vec<Duration> = samples.recent.iter().copied().collect();
+        recent.sort();
+        // Nearest-rank percentile, zero before the first request
+        let percentile = |p: f64| {
+            let rank = ((p * recent.len() as f64).ceil() as usize).max(1);
+            recent.get(rank - 1).copied().unwrap_or_default()
+        };
+        Stats {
+            count: samples.count,
+            sum: samples.sum,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+        }
+    }
+}
+
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    // Time until the response head is ready, so streamed bodies such as
+    // /stream and /history.csv only count until they start
+    let (mut parts, body) = request.into_parts();
+    let ip = ClientIp::from_request_parts(&mut parts, &()).await.ok();
+    let path = parts.uri.path().to_string();
+    let start = Instant::now();
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let elapsed = start.elapsed();
+    state.latency.record(elapsed);
+    let threshold = state.config.slow_request_ms;
+    if threshold > 0 && elapsed >= Duration::from_millis(threshold) {
+        let ip = ip.map(|ClientIp(ip)| ip.to_string()).unwrap_or_else(|| "unknown".to_string());
+        warn!("Slow request: {} from {} took {}ms", path, ip, elapsed.as_millis());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_percentiles() {
+        let latency = Latency::default();
+        assert_eq!(latency.stats().p95, Duration::ZERO);
+        for ms in 1..=100 {
+            latency.record(Duration::from_millis(ms));
+        }
+        let stats = latency.stats();
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.sum, Duration::from_millis(5050));
+        assert_eq!(stats.p50, Duration::from_millis(50));
+        assert_eq!(stats.p95, Duration::from_millis(95));
+    }
+}
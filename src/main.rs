@@ -1,25 +1,124 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::{BTreeMap, HashMap, VecDeque}, time::Duration};
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use std::path::PathBuf;
 // A Dashboard for my Raspberry PI which will display Component Temps, Fan speed, uptime etc.
-use axum::{Json, Router, extract::Query, routing::get, extract::ConnectInfo, middleware, extract};
-use axum::extract::FromRequestParts;
+use axum::{Json, Router, extract::Query, extract::State, routing::get, routing::post, middleware, extract};
+use axum::extract::{ConnectInfo, FromRequestParts};
 use axum::middleware::Next;
 use axum_client_ip::{ClientIp, ClientIpSource};
-use axum_server::tls_rustls;
 use axum_server::tls_rustls::RustlsConfig;
-use log::{error, info, trace};
-use rusqlite::{Connection, params};
+use log::{error, info, trace, warn};
+use rusqlite::{OptionalExtension, params};
+use serde::Serialize;
 use serde_json::{Value, json};
+use tokio::sync::watch;
 use tokio::time::sleep;
 use tracing_subscriber::{fmt, EnvFilter};
+use tower_http::compression::CompressionLayer;
 use tower_http::trace::TraceLayer;
 use tracing::{info_span, Span};
-use tower::{Layer, ServiceBuilder};
+use tower::ServiceBuilder;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use tower_http::cors::CorsLayer;
+use utoipa::ToSchema;
+
+mod alerts;
+mod all;
+mod anomaly;
+mod auth;
+mod cache;
+mod capabilities;
+mod cluster;
+mod collector;
+mod command;
+mod config;
+mod control;
+mod cors;
+mod dashboard;
+mod db;
+mod diag;
+mod disk_history;
+mod disk_wear;
+mod error;
+mod export;
+mod gpu;
+mod health;
+mod history_csv;
+mod history_events;
+mod history_summary;
+mod import;
+mod influx;
+mod latency;
+mod memory;
+mod metrics;
+mod mqtt;
+mod network;
+mod network_history;
+mod openapi;
+mod processes;
+mod ratelimit;
+mod retention;
+mod stream;
+mod system_info;
+mod temp_peak;
+#[cfg(test)]
+mod testing;
+mod thermal;
+mod vm;
+mod voltages;
+mod wifi;
+mod ws;
+
+use config::Config;
+use error::{ApiError, ApiResult};
+
+// Default seconds between two history rows written by the background logger
+pub const LOG_INTERVAL_SECS: u64 = 60;
+// Time between the two /proc/stat snapshots cpu_usage compares
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub cluster: Arc<cluster::Cluster>,
+    pub system_info: Arc<system_info::SystemInfo>,
+    pub cpu_cache: Arc<Mutex<CpuCache>>,
+    pub anomaly: Arc<Mutex<anomaly::Detector>>,
+    pub db: Arc<db::Db>,
+    pub temp_peak: Arc<Mutex<temp_peak::PeakCache>>,
+    pub collectors: Arc<collector::Registry>,
+    pub process_cache: Arc<Mutex<processes::ProcessCache>>,
+    pub rate_limiter: Arc<ratelimit::RateLimiter>,
+    pub metric_cache: Arc<cache::MetricCache>,
+    pub latency: Arc<latency::Latency>,
+    // History rows the database refused, written before the next row
+    pub pending_rows: Arc<Mutex<VecDeque<HistoryRow>>>,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> AppState {
+        let db = db::Db::new(&config.db_path).expect("Failed to open history database");
+        db::create_schema(&db.open().expect("Failed to open history database"));
+        let cpu_cache = Arc::new(Mutex::new(CpuCache::default()));
+        AppState {
+            collectors: Arc::new(collector::Registry::new(&config, cpu_cache.clone())),
+            cluster: Arc::new(cluster::Cluster::new(&config)),
+            system_info: Arc::new(system_info::SystemInfo::read()),
+            cpu_cache,
+            anomaly: Arc::new(Mutex::new(anomaly::Detector::new(&config))),
+            db: Arc::new(db),
+            temp_peak: Arc::new(Mutex::new(temp_peak::PeakCache::default())),
+            process_cache: Arc::new(Mutex::new(processes::ProcessCache::default())),
+            rate_limiter: Arc::new(ratelimit::RateLimiter::new(&config)),
+            metric_cache: Arc::new(cache::MetricCache::new(&config)),
+            latency: Arc::new(latency::Latency::default()),
+            pending_rows: Arc::new(Mutex::new(VecDeque::new())),
+            config: Arc::new(config),
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
@@ -31,25 +130,34 @@ async fn main() {
         )
         .with(fmt::layer())
         .init();
-    let conn = Connection::open("history.db").unwrap();
-    match conn.execute(
-        "CREATE TABLE IF NOT EXISTS 'values' (
-        id INTEGER PRIMARY KEY AUTOINCREMENT,
-        cpu_usage FLOAT,
-        mem_total INTEGER,
-        mem_used INTEGER,
-        disk_total INTEGER,
-        disk_used INTEGER,
-        disk_free INTEGER,
-        timestamp DATETIME DEFAULT CURRENT_TIMESTAMP
-        )",
-        (),
-    ) {
-        Ok(_) => {}
-        Err(err) => {
-            error!("Failed to create table: {}", err);
+    let args: Vec<String> = std::env::args().collect();
+    let (config_path, file) = match config::FileConfig::load(&args) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
         }
+    };
+    match config_path {
+        Some(path) => info!("Loaded config file {}", path),
+        None => info!("No config file, using defaults and environment variables"),
     }
+    let state = AppState::new(Config::from_file(file));
+    info!("Resolved config: {}", state.config.redacted());
+    info!("Using history database {}", state.config.db_path);
+    info!("Tracking disk writes on {}", state.config.sd_device);
+    info!("Reading CPU temperature from {}", state.config.thermal_zone);
+    // A malformed address must not silently fall back to listening everywhere
+    let addr = match state.config.listen_addr.parse::<SocketAddr>() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!(
+                "Invalid listen address {:?} ({}), expected ip:port such as 127.0.0.1:8443, check PIDASH_LISTEN, PIDASH_BIND_ADDR, PIDASH_PORT and the config file",
+                state.config.listen_addr, e
+            );
+            std::process::exit(1);
+        }
+    };
 
     let config = RustlsConfig::from_pem_file(
         PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -64,16 +172,63 @@ async fn main() {
 
     // build our application with a single route
     let app = Router::new()
-        .route("/cpu_temp", get(get_cpu_temp))
-        .route("/fan_speed", get(get_fan_speed))
-        .route("/uptime", get(get_uptime))
-        .route("/mem_usage", get(get_mem_usage))
-        .route("/disk_usage", get(get_disk_usage))
-        .route("/cpu_usage", get(get_cpu_usage))
+        .merge(state.collectors.routes())
+        .route("/metrics", get(metrics::get_metrics))
+        .route("/metrics/influx", get(metrics::get_influx))
+        .route("/cpu_temp/peak", get(temp_peak::get_temp_peak))
         .route("/history", get(get_history))
-        .layer(TraceLayer::new_for_http())
+        .route("/history.csv", get(history_csv::get_history_csv))
+        .route("/history/latest", get(get_history_latest))
+        .route("/history/disk", get(disk_history::get_disk_history))
+        .route("/history/anomalies", get(anomaly::get_anomalies))
+        .route("/history/events", get(history_events::get_history_events))
+        .route("/history/summary", get(history_summary::get_history_summary))
+        .route("/history/import", post(import::import_history))
+        .route("/history/export-file", post(export::export_history_file))
+        .route("/system/reboot", post(control::post_reboot))
+        .route("/system/shutdown", post(control::post_shutdown))
+        .route("/processes", get(processes::get_processes))
+        .route("/disk_writes", get(disk_wear::get_disk_writes))
+        .route("/cluster", get(cluster::get_cluster))
+        .route("/cluster/node", get(cluster::get_cluster_node))
+        .route("/system_info", get(system_info::get_system_info))
+        .route("/version", get(system_info::get_version))
+        .route("/vm", get(vm::get_vm))
+        .route("/mem_usage/detailed", get(memory::get_mem_detailed))
+        .route("/network/config", get(network::get_network_config))
+        .route("/network/interfaces", get(network::get_network_interfaces))
+        .route("/wifi", get(wifi::get_wifi))
+        .route("/diag", get(diag::get_diag))
+        .route("/capabilities", get(capabilities::get_capabilities))
+        .route("/throttled", get(thermal::get_throttled))
+        .route("/temps", get(thermal::get_temps))
+        .route("/cooling", get(thermal::get_cooling))
+        .route("/gpu", get(gpu::get_gpu))
+        .route("/voltages", get(voltages::get_voltages))
+        .route("/stream", get(stream::get_stream))
+        .route("/ws", get(ws::get_ws))
+        .route("/all", get(all::get_all))
+        .route("/openapi.json", get(openapi::get_openapi))
+        // Routes above need PIDASH_API_TOKEN when it is set, probes and the
+        // dashboard files below stay open
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_token))
+        .route("/health", get(health::get_health))
+        .route("/ready", get(health::get_ready))
+        .fallback_service(dashboard::service(&state.config))
+        // Paths only, a ?token= in the query must not reach the logs
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &http::Request<_>| {
+            info_span!("request", method = %request.method(), path = %request.uri().path(), version = ?request.version())
+        }))
         .layer(
             ServiceBuilder::new()
+                // A dual-stack listener sees IPv4 clients as ::ffff:a.b.c.d,
+                // report them by their IPv4 address
+                .layer(middleware::from_fn(async |mut request: extract::Request, next: Next| {
+                    if let Some(ConnectInfo(addr)) = request.extensions_mut().get_mut::<ConnectInfo<SocketAddr>>() {
+                        addr.set_ip(addr.ip().to_canonical());
+                    }
+                    next.run(request).await
+                }))
                 // Hardcode IP source, look into `examples/configurable.rs` for runtime
                 // configuration
                 .layer(ClientIpSource::ConnectInfo.into_extension())
@@ -83,7 +238,7 @@ async fn main() {
                         info_span!(
                             "request",
                             method = %request.method(),
-                            uri = %request.uri(),
+                            path = %request.uri().path(),
                             ip = tracing::field::Empty
                         )
                     }),
@@ -98,419 +253,1201 @@ async fn main() {
                         }
                         next.run(extract::Request::from_parts(parts, body)).await
                     },
-                )),
+                ))
+                .layer(middleware::from_fn_with_state(state.clone(), ratelimit::limit))
+                // Inside the rate limit, so rejected requests don't count
+                .layer(middleware::from_fn_with_state(state.clone(), latency::track)),
         )
-        .layer(CorsLayer::new()
-            .allow_origin(tower_http::cors::Any)
-            .allow_methods(tower_http::cors::Any)
-            .allow_headers(tower_http::cors::Any));
+        // gzip or brotli when the client accepts it. The default predicate leaves
+        // out text/event-stream, so /stream events are not held back in the encoder.
+        .layer(CompressionLayer::new())
+        .layer(cors::layer(&state.config))
+        .with_state(state.clone());
 
-    // spawn thread to handle database operations
+    // Ctrl-C or SIGTERM (systemd stop) stops the server and the logger
+    let handle = axum_server::Handle::new();
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+    let shutdown_handle = handle.clone();
     tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down");
+        shutdown_handle.graceful_shutdown(Some(Duration::from_secs(10)));
+        let _ = shutdown_tx.send(true);
+    });
+
+    // spawn thread to handle database operations
+    let logger = tokio::spawn(async move {
+        let influx = influx::Pusher::new(&state.config);
+        let mqtt = mqtt::Publisher::new(&state);
+        let alerter = alerts::Alerter::new(&state.config, &state.collectors);
+        // Take the first CPU snapshot now so the first row logs usage over the
+        // whole warmup delay
+        state.cpu_cache.lock().unwrap().update();
+        info!("Logging history every {}s", state.config.log_interval_secs);
+        info!("Waiting {}s before logging the first history row", state.config.startup_delay_secs);
+        tokio::select! {
+            _ = sleep(Duration::from_secs(state.config.startup_delay_secs)) => {}
+            _ = shutdown_rx.changed() => return,
+        }
         loop {
             // log cpu usage and memory usage history in database
-            value_logging();
-            sleep(Duration::from_secs(60)).await;
+            let sample = log_values(&state).await;
+            if let Some(mqtt) = &mqtt {
+                mqtt.publish(&state, &sample);
+            }
+            if let Some(influx) = &influx {
+                influx.push(&state, &sample).await;
+            }
+            if let Some(alerter) = &alerter {
+                alerter.check(&sample).await;
+            }
+            tokio::select! {
+                _ = sleep(Duration::from_secs(state.config.log_interval_secs)) => {}
+                _ = shutdown_rx.changed() => {
+                    // Log the partial interval so no data is lost, the
+                    // connection is closed when value_logging returns
+                    log_values(&state).await;
+                    info!("Logger stopped");
+                    return;
+                }
+            }
         }
     });
-    let addr = SocketAddr::from(([0, 0, 0, 0], 80));
-    info!("Starting server on https://{}", addr);
-    axum_server::bind_rustls(addr, config)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-}
-async fn get_cpu_temp() -> Json<Value> {
-    // Read CPU temperature from the thermal zone file
-    trace!("Reading CPU temperature from thermal zone file");
-    let thermal_zone = "/sys/class/thermal/thermal_zone0/temp";
-    let temp = std::fs::read_to_string(thermal_zone);
-    let temp = match temp {
-        Ok(t) => t,
+    let listener = match listener(addr) {
+        Ok(listener) => listener,
         Err(e) => {
-            error!("Failed to read CPU temperature: {}", e);
-            return Json(json!({"error": "Failed to read CPU temperature"}));
+            error!("Failed to listen on {}: {}", addr, e);
+            std::process::exit(1);
         }
     };
-    trace!("CPU temperature read successfully: {}", temp);
-    let json = json!({
-        "cpu_temp": temp.trim().parse::<i32>().unwrap()
-    });
-    Json(json)
+    info!("Starting server on https://{}", addr);
+    axum_server::from_tcp_rustls(listener, config)
+        .handle(handle)
+        // ClientIpSource::ConnectInfo reads the peer address from the connect info
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
+    // Wait for the final history row before exiting
+    if let Err(e) = logger.await {
+        error!("Logger task failed: {}", e);
+    }
 }
 
-async fn get_fan_speed() -> Json<Value> {
-    // Read fan speed from the hardware monitor file
-    trace!("Reading fan speed from hardware monitor file");
-    let fan_speed = "/sys/devices/platform/cooling_fan/hwmon/hwmon2/fan1_input";
-    let speed = std::fs::read_to_string(fan_speed);
-    let speed = match speed {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Failed to read fan speed: {}", e);
-            return Json(json!({"error": "Failed to read fan speed"}));
-        }
-    };
-    trace!("Fan speed read successfully: {}", speed);
-    let json = json!({
-        "fan_speed": speed.trim().parse::<i32>().unwrap()
-    });
-    Json(json)
+fn listener(addr: SocketAddr) -> std::io::Result<std::net::TcpListener> {
+    // An IPv6 address such as [::] also accepts IPv4 clients, whatever the
+    // system's net.ipv6.bindv6only default is
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::STREAM, Some(socket2::Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
 }
 
-async fn get_uptime() -> Json<Value> {
-    // Read system uptime from the /proc/uptime file
-    trace!("Reading system uptime from /proc/uptime file");
-    let uptime = "/proc/uptime";
-    let uptime_str = std::fs::read_to_string(uptime);
-    let uptime_str = match uptime_str {
-        Ok(u) => u,
-        Err(e) => {
-            error!("Failed to read system uptime: {}", e);
-            return Json(json!({"error": "Failed to read system uptime"}));
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
         }
     };
-    trace!("System uptime read successfully: {}", uptime_str);
-    let uptime_secs = uptime_str
-        .split_whitespace()
-        .next();
-    let uptime_secs = match uptime_secs {
-        Some(u) => u.parse::<f64>().unwrap_or(0.0),
-        None => {
-            error!("Failed to parse system uptime");
-            return Json(json!({"error": "Failed to parse system uptime"}));
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
         }
     };
-    trace!("System uptime in seconds: {}", uptime_secs);
-    // Convert uptime from seconds to milliseconds
-    let uptime_millis = (uptime_secs * 1000.0).floor() as i64;
-    let json = json!({
-        "uptime": uptime_millis
-    });
-    Json(json)
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+fn read_cpu_temp(config: &Config) -> Option<i32> {
+    // CPU temperature in millidegrees Celsius, None if the sensor can't be read
+    std::fs::read_to_string(&config.thermal_zone)
+        .ok()?
+        .trim()
+        .parse::<i32>()
+        .ok()
 }
 
-async fn get_mem_usage() -> Json<Value> {
-    // Read memory usage from the /proc/meminfo file
-    trace!("Fetching memory usage for http request");
-    let (mem_total, mem_used) = mem_usage();
-    let json = json!({
-        "mem_used": mem_used,
-        "mem_total": mem_total,
-        "mem_percent": ((mem_used as f64 / mem_total as f64 * 100.0).round() as i32)
-    });
-    Json(json)
-}
-
-async fn get_disk_usage() -> Json<Value> {
-    // Read disk usage from the df command output
-    trace!("Fetching disk usage for http request");
-    let (total, used, free) = disk_usage();
-    let json = json!({
-        "total": total,
-        "used": used,
-        "free": free,
-        "percent": ((used.parse::<f64>().unwrap() / total.parse::<f64>().unwrap() * 100.0).round() as i32)
-    });
-    Json(json)
+fn cpu_usage() -> f64 {
+    // CPU usage over a short window between two /proc/stat snapshots, a single
+    // snapshot would only give the average since boot
+    let first = cpu_times();
+    std::thread::sleep(CPU_SAMPLE_INTERVAL);
+    match (first, cpu_times()) {
+        (Some(first), Some(second)) => {
+            let cpu_usage = usage_between(first, second).unwrap_or(0.0);
+            trace!("Calculated CPU usage: {}", cpu_usage);
+            cpu_usage
+        }
+        _ => 0.0, // Return 0.0 if reading fails
+    }
 }
 
-async fn get_cpu_usage() -> Json<Value> {
-    // Read CPU usage from the /proc/stat file
-    trace!("Fetching CPU usage for http request");
-    let cpu_usage = cpu_usage();
-    let json = json!({
-        "cpu_usage": cpu_usage
-    });
-    Json(json)
+// Share of each kind of CPU time over the same short window as cpu_usage, in percent
+fn cpu_breakdown() -> Result<BTreeMap<String, f64>, String> {
+    let first = cpu_fields().ok_or("Failed to read CPU times")?;
+    std::thread::sleep(CPU_SAMPLE_INTERVAL);
+    let second = cpu_fields().ok_or("Failed to read CPU times")?;
+    breakdown_between(&first, &second).ok_or_else(|| "No CPU time passed between samples".to_string())
 }
 
-fn cpu_usage() -> f64 {
-    // Read CPU usage from the /proc/stat file
-    trace!("Reading CPU usage from /proc/stat file");
-    let cpuinfo = "/proc/stat";
-    let cpuinfo_str = std::fs::read_to_string(cpuinfo);
-    let cpuinfo_str = match cpuinfo_str {
-        Ok(c) => c,
+fn cpu_times() -> Option<(f64, f64)> {
+    cpu_fields().map(|fields| total_and_idle(&fields))
+}
+
+fn cpu_fields() -> Option<Vec<f64>> {
+    // Read the aggregate CPU times from the /proc/stat file
+    trace!("Reading CPU times from /proc/stat file");
+    let stat = match std::fs::read_to_string("/proc/stat") {
+        Ok(stat) => stat,
         Err(e) => {
             error!("Failed to read CPU usage: {}", e);
-            return 0.0; // Return 0.0 if reading fails
-        }
-    };
-    trace!("CPU usage read successfully: {}", cpuinfo_str);
-    let cpu_user = cpuinfo_str
-        .lines()
-        .find(|line| line.starts_with("cpu "))
-        .unwrap()
-        .split_whitespace()
-        .nth(1);
-    let cpu_user = match cpu_user {
-        Some(u) => u.parse::<f64>().unwrap_or(0.0),
-        None => {
-            error!("Failed to parse CPU user time");
-            return 0.0; // Return 0.0 if parsing fails
-        }
-    };
-    let cpu_nice = cpuinfo_str
-        .lines()
-        .find(|line| line.starts_with("cpu "))
-        .unwrap()
-        .split_whitespace()
-        .nth(2);
-    let cpu_nice = match cpu_nice {
-        Some(n) => n.parse::<f64>().unwrap_or(0.0),
-        None => {
-            error!("Failed to parse CPU nice time");
-            return 0.0; // Return 0.0 if parsing fails
-        }
-    };
-    let cpu_system = cpuinfo_str
-        .lines()
-        .find(|line| line.starts_with("cpu "))
-        .unwrap()
-        .split_whitespace()
-        .nth(3);
-    let cpu_system = match cpu_system {
-        Some(s) => s.parse::<f64>().unwrap_or(0.0),
-        None => {
-            error!("Failed to parse CPU system time");
-            return 0.0; // Return 0.0 if parsing fails
-        }
-    };
-    let cpu_idle = cpuinfo_str
-        .lines()
-        .find(|line| line.starts_with("cpu "))
-        .unwrap()
-        .split_whitespace()
-        .nth(4);
-    let cpu_idle = match cpu_idle {
-        Some(i) => i.parse::<f64>().unwrap_or(0.0),
-        None => {
-            error!("Failed to parse CPU idle time");
-            return 0.0; // Return 0.0 if parsing fails
+            return None; // Return None if reading fails
         }
     };
-    let cpu_iowait = cpuinfo_str
-        .lines()
-        .find(|line| line.starts_with("cpu "))
-        .unwrap()
-        .split_whitespace()
-        .nth(5);
-    let cpu_iowait = match cpu_iowait {
-        Some(i) => i.parse::<f64>().unwrap_or(0.0),
-        None => {
-            error!("Failed to parse CPU iowait time");
-            return 0.0; // Return 0.0 if parsing fails
-        }
-    };
-    let cpu_irq = cpuinfo_str
+    let fields = parse_cpu_fields(&stat);
+    if fields.is_none() {
+        error!("Failed to parse CPU times from /proc/stat");
+    }
+    fields
+}
+
+// Fields of the aggregate "cpu " line of /proc/stat, in order. guest and
+// guest_nice follow but are already counted in user and nice.
+const CPU_TIME_FIELDS: [&str; 8] = ["user", "nice", "system", "idle", "iowait", "irq", "softirq", "steal"];
+
+fn parse_cpu_fields(stat: &str) -> Option<Vec<f64>> {
+    let mut fields: Vec<f64> = stat
         .lines()
-        .find(|line| line.starts_with("cpu "))
-        .unwrap()
+        .find(|line| line.starts_with("cpu "))?
         .split_whitespace()
-        .nth(6);
-    let cpu_irq = match cpu_irq {
-        Some(i) => i.parse::<f64>().unwrap_or(0.0),
-        None => {
-            error!("Failed to parse CPU irq time");
-            return 0.0; // Return 0.0 if parsing fails
+        .skip(1)
+        .take(CPU_TIME_FIELDS.len())
+        .map(|field| field.parse::<f64>().ok())
+        .collect::<Option<_>>()?;
+    // Kernels before 2.6.11 have no steal field
+    if fields.len() < 7 {
+        return None;
+    }
+    fields.resize(CPU_TIME_FIELDS.len(), 0.0);
+    trace!("CPU times - {:?}", CPU_TIME_FIELDS.iter().zip(&fields).collect::<Vec<_>>());
+    Some(fields)
+}
+
+fn total_and_idle(fields: &[f64]) -> (f64, f64) {
+    // Total and idle jiffies. Steal is time a hypervisor gave to another guest,
+    // so it counts as busy like the rest.
+    (fields.iter().sum(), fields[3])
+}
+
+// Percentage of the elapsed jiffies spent in each kind of CPU time
+fn breakdown_between(first: &[f64], second: &[f64]) -> Option<BTreeMap<String, f64>> {
+    let deltas: Vec<f64> = second.iter().zip(first).map(|(b, a)| b - a).collect();
+    let total: f64 = deltas.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+    Some(
+        CPU_TIME_FIELDS
+            .iter()
+            .zip(deltas)
+            .map(|(name, delta)| (name.to_string(), delta / total * 100.0))
+            .collect(),
+    )
+}
+
+// Percentage of non-idle time between two (total, idle) samples
+fn usage_between(first: (f64, f64), second: (f64, f64)) -> Option<f64> {
+    let total_delta = second.0 - first.0;
+    if total_delta <= 0.0 {
+        return None;
+    }
+    Some((total_delta - (second.1 - first.1)) / total_delta * 100.0)
+}
+
+// Latest CPU usage computed by the logger from the delta between two log cycles
+#[derive(Default)]
+pub struct CpuCache {
+    times: Option<(f64, f64)>,
+    usage: Option<f64>,
+    updated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl CpuCache {
+    fn update(&mut self) {
+        let times = cpu_times();
+        if let (Some(previous), Some(current)) = (self.times, times)
+            && let Some(usage) = usage_between(previous, current)
+        {
+            trace!("Cached CPU usage updated: {}", usage);
+            self.usage = Some(usage);
+            self.updated_at = Some(chrono::Utc::now());
         }
-    };
-    let cpu_softirq = cpuinfo_str
-        .lines()
-        .find(|line| line.starts_with("cpu "))
-        .unwrap()
-        .split_whitespace()
-        .nth(7);
-    let cpu_softirq = match cpu_softirq {
-        Some(s) => s.parse::<f64>().unwrap_or(0.0),
-        None => {
-            error!("Failed to parse CPU softirq time");
-            return 0.0; // Return 0.0 if parsing fails
+        self.times = times;
+    }
+
+    // Keeps a fallback sample unless an interval delta arrived meanwhile
+    fn store_sample(&mut self, usage: f64) {
+        if self.usage.is_none() {
+            self.usage = Some(usage);
+            self.updated_at = Some(chrono::Utc::now());
         }
-    };
-    trace!("CPU times - User: {}, Nice: {}, System: {}, Idle: {}, Iowait: {}, Irq: {}, Softirq: {}",
-           cpu_user, cpu_nice, cpu_system, cpu_idle, cpu_iowait, cpu_irq, cpu_softirq);
-    let cpu_total = cpu_user + cpu_system + cpu_iowait + cpu_irq + cpu_softirq + cpu_nice + cpu_idle;
-    let cpu_usage = (cpu_total - cpu_idle) / cpu_total * 100.0;
-    trace!("Calculated CPU usage: {}", cpu_usage);
-    cpu_usage
+    }
 }
 
 fn mem_usage() -> (i32, i32) {
     // Read memory usage from the /proc/meminfo file
     trace!("Reading memory usage from /proc/meminfo file");
-    let meminfo = "/proc/meminfo";
-    let meminfo_str = std::fs::read_to_string(meminfo);
-    let meminfo_str = match meminfo_str {
-        Ok(m) => m,
+    let meminfo = match std::fs::read_to_string("/proc/meminfo") {
+        Ok(meminfo) => meminfo,
         Err(e) => {
             error!("Failed to read memory usage: {}", e);
             return (0, 0); // Return (0, 0) if reading fails
         }
     };
-    let mem_total = meminfo_str
-        .lines()
-        .find(|line| line.starts_with("MemTotal:"))
-        .unwrap()
-        .split_whitespace()
-        .nth(1);
-    let mem_total = match mem_total {
-        Some(t) => t.parse::<i32>().unwrap_or(0),
-        None => {
-            error!("Failed to parse memory total");
-            return (0, 0); // Return (0, 0) if parsing fails
-        }
-    };
-    let mem_avail = meminfo_str
-        .lines()
-        .find(|line| line.starts_with("MemAvailable:"))
-        .unwrap()
-        .split_whitespace()
-        .nth(1);
-    let mem_avail = match mem_avail {
-        Some(a) => a.parse::<i32>().unwrap_or(0),
-        None => {
-            error!("Failed to parse memory available");
-            return (0, 0); // Return (0, 0) if parsing fails
-        }
+    let (Some(mem_total), Some(mem_avail)) = (meminfo_kb(&meminfo, "MemTotal:"), meminfo_kb(&meminfo, "MemAvailable:")) else {
+        error!("Failed to parse memory usage from /proc/meminfo");
+        return (0, 0); // Return (0, 0) if parsing fails
     };
     trace!("Memory - Total: {}, Available: {}", mem_total, mem_avail);
-    let mem_used = mem_total - mem_avail;
+    let mem_used = mem_total.saturating_sub(mem_avail);
     trace!("Calculated memory usage: Used: {}, Total: {}", mem_used, mem_total);
-    (mem_total, mem_used)
-}
-fn disk_usage() -> (String, String, String) {
-    // Read disk usage from the df command output
-    trace!("Running df command to get disk usage");
-    let df_output = std::process::Command::new("df").output();
-    let df_output = match df_output {
-        Ok(output) => output,
-        Err(e) => {
-            error!("Failed to run df command: {}", e);
-            return ("0".to_string(), "0".to_string(), "0".to_string()); // Return (0, 0, 0) if running fails
-        }
-    };
-    if !df_output.status.success() {
-        error!("df command failed with status: {}", df_output.status);
-        return ("0".to_string(), "0".to_string(), "0".to_string()); // Return (0, 0, 0) if command fails
-    }
-    trace!("df command executed successfully, processing output");
-    let df_str = String::from_utf8_lossy(&df_output.stdout);
-    let df_lines: Vec<&str> = df_str.lines().collect();
+    (mem_total as i32, mem_used as i32)
+}
 
-    // Get the root filesystem line (typically the first filesystem after headers)
-    let root_line = df_lines.iter().skip(3).next().unwrap_or(&df_lines[1]);
+// Value of a /proc/meminfo line such as "MemTotal:  3884096 kB", in kB
+fn meminfo_kb(meminfo: &str, key: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix(key))?
+        .split_whitespace()
+        .next()?
+        .parse::<u64>()
+        .ok()
+}
+
+fn swap_usage() -> Result<(u64, u64), String> {
+    // Read total and used swap in kilobytes from the /proc/meminfo file, both
+    // are 0 when swap is disabled
+    trace!("Reading swap usage from /proc/meminfo file");
+    let meminfo = std::fs::read_to_string("/proc/meminfo").map_err(|e| {
+        error!("Failed to read swap usage: {}", e);
+        "Failed to read swap usage".to_string()
+    })?;
+    let field = |key: &str| meminfo_kb(&meminfo, key).ok_or_else(|| format!("Failed to parse {} from /proc/meminfo", key));
+    let swap_total = field("SwapTotal:")?;
+    let swap_free = field("SwapFree:")?;
+    let swap_used = swap_total.saturating_sub(swap_free);
+    trace!("Swap usage - Total: {}, Used: {}", swap_total, swap_used);
+    Ok((swap_total, swap_used))
+}
 
-    let parts: Vec<&str> = root_line.split_whitespace().collect();
-    let total = parts[1].to_string();
-    let used = parts[2].to_string();
-    let free = parts[3].to_string();
+pub fn disk_usage(mount: &str) -> Result<(u64, u64, u64), String> {
+    filesystem_usage(mount).map(|(disk, _)| disk)
+}
+
+// Total, used and free
+type Usage = (u64, u64, u64);
+
+fn filesystem_usage(path: &str) -> Result<(Usage, Usage), String> {
+    // Read total, used and free kilobytes of the filesystem containing path,
+    // the same figures df reports in its 1K-blocks, Used and Available columns,
+    // and its inode counts from the same statvfs call. A filesystem can run out
+    // of inodes while still having free space.
+    trace!("Reading disk usage for {}", path);
+    let stat = nix::sys::statvfs::statvfs(path).map_err(|e| format!("Failed to statvfs {}: {}", path, e))?;
+    let block_size = stat.fragment_size() as u64;
+    let total = stat.blocks() as u64 * block_size / 1024;
+    let used = (stat.blocks() as u64).saturating_sub(stat.blocks_free() as u64) * block_size / 1024;
+    let free = stat.blocks_available() as u64 * block_size / 1024;
     trace!("Disk usage - Total: {}, Used: {}, Free: {}", total, used, free);
-    (total, used, free)
+    let inodes_total = stat.files() as u64;
+    let inodes_free = stat.files_free() as u64;
+    let inodes_used = inodes_total.saturating_sub(inodes_free);
+    trace!("Inode usage - Total: {}, Used: {}, Free: {}", inodes_total, inodes_used, inodes_free);
+    Ok(((total, used, free), (inodes_total, inodes_used, inodes_free)))
 }
 
-fn value_logging() {
+async fn log_values(state: &AppState) -> HashMap<&'static str, Value> {
+    // Collectors read files and run commands, and the database write retries
+    // with a sleep, so the cycle runs off the runtime
+    let task_state = state.clone();
+    tokio::task::spawn_blocking(move || value_logging(&task_state))
+        .await
+        .unwrap_or_else(|e| {
+            error!("Logger task failed: {}", e);
+            HashMap::new()
+        })
+}
+
+// Returns the collected sample, for outputs other than the database
+fn value_logging(state: &AppState) -> HashMap<&'static str, Value> {
     info!("Logging CPU and memory usage to database");
     trace!("Starting value logging process");
     //log cpu usage and memory usage history in database
-    let cpu_usage = cpu_usage();
+    let cached = {
+        let mut cache = state.cpu_cache.lock().unwrap();
+        cache.update();
+        cache.usage
+    };
+    // Fall back to a short sample if no interval delta is available yet,
+    // taken without the lock so cached reads don't wait on it
+    let cpu_usage = cached.unwrap_or_else(|| {
+        let usage = cpu_usage();
+        state.cpu_cache.lock().unwrap().store_sample(usage);
+        usage
+    });
     trace!("Logging CPU usage: {}", cpu_usage);
-    let mem_usage = mem_usage();
+    // The other metrics come from the collectors, a metric that can't be read
+    // is logged as NULL rather than as zero
+    let mut sample = state.collectors.collect_logged();
+    sample.insert("cpu_usage", json!({ "cpu_usage": cpu_usage }));
+    let field = |collector: &str, key: &str| -> Option<i64> {
+        sample.get(collector)?[key].as_i64()
+    };
+    // History keeps the raw millidegrees
+    let cpu_temp = field("cpu_temp", "raw").map(|t| t as i32);
+    trace!("Logging CPU temperature: {:?}", cpu_temp);
+    // NULL on boards without a fan
+    let fan_speed = field("fan_speed", "fan_speed");
+    trace!("Logging fan speed: {:?}", fan_speed);
+    let mem_usage = (field("mem_usage", "mem_total").unwrap_or(0), field("mem_usage", "mem_used").unwrap_or(0));
     trace!("Logging memory usage: Total: {}, Used: {}", mem_usage.0, mem_usage.1);
-    let disk_usage = disk_usage();
-    trace!("Logging disk usage: Total: {}, Used: {}, Free: {}", disk_usage.0, disk_usage.1, disk_usage.2);
+    // History keeps disk sizes in kilobytes, disk_usage reports bytes
+    let disk_kb = |key: &str| field("disk_usage", key).map(|bytes| bytes / 1024);
+    let disk_usage = (disk_kb("total"), disk_kb("used"), disk_kb("free"));
+    trace!("Logging disk usage: Total: {:?}, Used: {:?}, Free: {:?}", disk_usage.0, disk_usage.1, disk_usage.2);
+    let anomaly = {
+        let mut detector = state.anomaly.lock().unwrap();
+        // Check every metric so each one's window keeps advancing
+        let cpu_anomaly = detector.check("cpu_usage", cpu_usage);
+        let mem_anomaly = detector.check("mem_used", mem_usage.1 as f64);
+        cpu_anomaly || mem_anomaly
+    };
     // log cpu_usage, mem_usage, and disk_usage to database
-    let conn = Connection::open("history.db");
+    let timestamp = chrono::Utc::now().format(TIMESTAMP_FORMAT).to_string();
+    // Written from a local queue, the lock isn't held while the database is busy
+    let mut pending = std::mem::take(&mut *state.pending_rows.lock().unwrap());
+    pending.push_back(HistoryRow {
+        cpu_usage,
+        mem_total: mem_usage.0,
+        mem_used: mem_usage.1,
+        disk_total: disk_usage.0,
+        disk_used: disk_usage.1,
+        disk_free: disk_usage.2,
+        anomaly,
+        cpu_temp,
+        fan_speed,
+        timestamp: timestamp.clone(),
+        id: None,
+        // Kept with the row, so they are written with it even when it is late
+        network: sample.get("network").map(network_history::counters),
+    });
+    let conn = state.db.open();
     let conn = match conn {
         Ok(conn) => conn,
         Err(e) => {
             error!("Failed to open database: {}", e);
-            return; // Exit if database connection fails
+            keep_pending(state, pending);
+            return sample; // Exit if database connection fails
         }
     };
-    let res = conn.execute(
-        "INSERT INTO 'values' (cpu_usage, mem_total, mem_used, disk_total, disk_used, disk_free) VALUES (?, ?, ?, ?, ?, ?)",
-        params![cpu_usage, mem_usage.0, mem_usage.1, disk_usage.0, disk_usage.1, disk_usage.2],
-    );
-    match res {
-        Ok(_) => {
-            trace!("Values logged successfully to database");
+    // Oldest first, so a row is never written before one that was refused
+    while let Some(row) = pending.front() {
+        match insert_row(&conn, row) {
+            Ok(()) => {
+                trace!("Values logged successfully to database");
+                if let Some(temp) = row.cpu_temp {
+                    state.temp_peak.lock().unwrap().record(temp, &row.timestamp);
+                }
+                pending.pop_front();
+            }
+            Err(e) => {
+                error!("Failed to log values to database, keeping {} rows for the next cycle: {}", pending.len(), e);
+                break;
+            }
         }
-        Err(e) => {
-            error!("Failed to log values to database: {}", e);
+    }
+    keep_pending(state, pending);
+    disk_history::log_mounts(&conn, &state.config.history_mounts);
+    // keep the persisted disk write total in step with the kernel counter
+    if let Err(e) = disk_wear::update(&conn, &state.config) {
+        error!("{}", e);
+    }
+    if let Err(e) = retention::prune(&conn, state.config.retention_days) {
+        error!("{}", e);
+    }
+    sample
+}
+
+// Attempts at writing a history row before it is kept for the next cycle
+const INSERT_ATTEMPTS: u32 = 3;
+const INSERT_RETRY_DELAY: Duration = Duration::from_millis(100);
+// History rows kept while the database refuses writes, about an hour at the
+// default interval. The oldest are dropped beyond it.
+const MAX_PENDING_ROWS: usize = 60;
+
+// A row of the values table, kept in memory until it is written and returned
+// by /history
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryRow {
+    cpu_usage: f64,
+    mem_total: i64,
+    mem_used: i64,
+    disk_total: Option<i64>,
+    disk_used: Option<i64>,
+    disk_free: Option<i64>,
+    anomaly: bool,
+    cpu_temp: Option<i32>,
+    fan_speed: Option<i64>,
+    timestamp: String,
+    // Row id, which network counters are linked to
+    #[serde(skip)]
+    id: Option<i64>,
+    // Per-interface counters of the same cycle, with ?include_network=true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(inline)]
+    network: Option<Vec<network_history::InterfaceCounters>>,
+}
+
+// Averages of the rows in a bucket of /history?bucket=
+#[derive(Serialize, ToSchema)]
+pub struct HistoryBucket {
+    timestamp: String,
+    cpu_usage: Option<f64>,
+    mem_used: Option<f64>,
+    disk_used: Option<f64>,
+    count: i64,
+}
+
+// Rows, or bucket averages when a bucket width was asked for, newest first
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum HistoryData {
+    Rows(Vec<HistoryRow>),
+    Buckets(Vec<HistoryBucket>),
+}
+
+#[derive(Serialize, ToSchema)]
+struct HistoryResponse {
+    data: HistoryData,
+    timezone: &'static str,
+    // Tells the client its limit was lowered rather than silently returning fewer rows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit_clamped_to: Option<usize>,
+}
+
+fn insert_row(conn: &rusqlite::Connection, row: &HistoryRow) -> rusqlite::Result<()> {
+    // Retry briefly, a write can still lose to a long read past the busy timeout
+    let mut attempt = 1;
+    loop {
+        match insert_values(conn, row) {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < INSERT_ATTEMPTS => {
+                warn!("Failed to log values to database, attempt {} of {}: {}", attempt, INSERT_ATTEMPTS, e);
+                std::thread::sleep(INSERT_RETRY_DELAY);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
         }
     }
 }
 
-async fn get_history(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
-    // Handle history requests with optional query parameters
-    trace!("Fetching history data with parameters: {:?}", params);
-    // Extract from and to dates from query parameters or use defaults (1970-01-01T00:00:00Z)
-    let first = "1970-01-01T00:00:00Z".to_string();
-    let last = "now".to_string();
-    let from = params.get("from").unwrap_or(&first);
-    let to = params.get("to").unwrap_or(&last);
-    let limit = params.get("limit").and_then(|s| s.parse::<usize>().ok()).unwrap_or(100);
+fn insert_values(conn: &rusqlite::Connection, row: &HistoryRow) -> rusqlite::Result<()> {
+    // The network counters are written with their row or not at all
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO 'values' (cpu_usage, mem_total, mem_used, disk_total, disk_used, disk_free, anomaly, cpu_temp, fan_speed, timestamp) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![row.cpu_usage, row.mem_total, row.mem_used, row.disk_total, row.disk_used, row.disk_free, row.anomaly, row.cpu_temp, row.fan_speed, row.timestamp],
+    )?;
+    network_history::insert(&tx, tx.last_insert_rowid(), &row.timestamp, row.network.as_deref().unwrap_or_default())?;
+    tx.commit()
+}
 
-    let conn = match Connection::open("history.db") {
-        Ok(conn) => conn,
-        Err(e) => {
-            error!("Failed to open database: {}", e);
-            return Json(json!({
-                "error": format!("Failed to open database: {}", e)
-            }));
+fn keep_pending(state: &AppState, mut rows: VecDeque<HistoryRow>) {
+    // Rows that weren't written go back ahead of any queued meanwhile
+    let mut pending = state.pending_rows.lock().unwrap();
+    rows.append(&mut pending);
+    trim_pending(&mut rows);
+    *pending = rows;
+}
+
+fn trim_pending(pending: &mut VecDeque<HistoryRow>) {
+    if pending.len() > MAX_PENDING_ROWS {
+        let dropped = pending.len() - MAX_PENDING_ROWS;
+        pending.drain(..dropped);
+        error!("Dropped {} unwritten history rows, keeping the latest {}", dropped, MAX_PENDING_ROWS);
+    }
+}
+
+// Columns selected from the values table, in the order history_row reads them
+const HISTORY_COLUMNS: &str = "cpu_usage, mem_total, mem_used, disk_total, disk_used, disk_free, timestamp, anomaly, cpu_temp, fan_speed, id";
+
+fn history_row(row: &rusqlite::Row) -> rusqlite::Result<HistoryRow> {
+    Ok(HistoryRow {
+        cpu_usage: row.get(0)?,
+        mem_total: row.get(1)?,
+        mem_used: row.get(2)?,
+        disk_total: row.get(3)?,
+        disk_used: row.get(4)?,
+        disk_free: row.get(5)?,
+        timestamp: row.get(6)?,
+        anomaly: row.get(7)?,
+        cpu_temp: row.get(8)?,
+        fan_speed: row.get(9)?,
+        id: row.get(10)?,
+        network: None,
+    })
+}
+
+// Format of the timestamp column, SQLite's CURRENT_TIMESTAMP in UTC
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+pub fn history_bound(bound: &str) -> Result<String, String> {
+    // Bring a from/to query parameter into the stored timestamp format, so the
+    // string comparison in BETWEEN orders it correctly. Accepts `now`, RFC 3339
+    // with an offset, and date-times with a T or a space in UTC or a bare date.
+    let bound = bound.trim();
+    if bound == "now" {
+        return Ok(chrono::Utc::now().format(TIMESTAMP_FORMAT).to_string());
+    }
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(bound) {
+        return Ok(time.with_timezone(&chrono::Utc).format(TIMESTAMP_FORMAT).to_string());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(time) = chrono::NaiveDateTime::parse_from_str(bound, format) {
+            return Ok(time.format(TIMESTAMP_FORMAT).to_string());
         }
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(bound, "%Y-%m-%d") {
+        return Ok(format!("{} 00:00:00", date));
+    }
+    Err(format!("Invalid timestamp {:?}, expected e.g. 2024-01-01T00:00:00Z or 2024-01-01 00:00:00", bound))
+}
+
+fn parse_bucket(bucket: &str) -> Option<i64> {
+    // Bucket width such as 30s, 5m, 1h or 1d in seconds
+    let split = bucket.len().checked_sub(1)?;
+    let (count, unit) = bucket.split_at_checked(split)?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
     };
-    trace!("Preparing to query history data from database with from: {}, to: {}, limit: {}", from, to, limit);
+    count.parse::<i64>().ok().filter(|&count| count > 0)?.checked_mul(unit)
+}
 
-    let mut stmt = match conn.prepare("SELECT cpu_usage, mem_total, mem_used, disk_total, disk_used, disk_free, timestamp FROM 'values' WHERE timestamp BETWEEN ? AND ? ORDER BY timestamp DESC LIMIT ?") {
-        Ok(stmt) => stmt,
-        Err(e) => {
-            error!("Failed to prepare statement: {}", e);
-            return Json(json!({
-                "error": format!("Failed to prepare statement: {}", e)
-            }));
+fn history_buckets(conn: &rusqlite::Connection, from: &str, to: &str, limit: usize, secs: i64) -> Result<Vec<HistoryBucket>, String> {
+    // Average the history over fixed buckets aligned to the Unix epoch, newest
+    // first. `count` is the number of rows in a bucket, so gaps show up as low
+    // counts and empty buckets are missing.
+    let mut stmt = conn
+        .prepare(
+            "SELECT datetime(CAST(strftime('%s', timestamp) AS INTEGER) / ?1 * ?1, 'unixepoch') AS bucket,
+            AVG(cpu_usage), AVG(mem_used), AVG(disk_used), COUNT(*)
+            FROM 'values' WHERE timestamp BETWEEN ?2 AND ?3
+            GROUP BY bucket ORDER BY bucket DESC LIMIT ?4",
+        )
+        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let rows = stmt
+        .query_map(params![secs, from, to, limit], |row| {
+            Ok(HistoryBucket {
+                timestamp: row.get(0)?,
+                cpu_usage: row.get(1)?,
+                mem_used: row.get(2)?,
+                disk_used: row.get(3)?,
+                count: row.get(4)?,
+            })
+        })
+        .map_err(|e| format!("Query execution failed: {}", e))?;
+    rows.collect::<Result<Vec<HistoryBucket>, _>>()
+        .map_err(|e| format!("Error processing row: {}", e))
+}
+
+fn history_limit(params: &HashMap<String, String>, max: usize) -> Result<(usize, bool), String> {
+    // Rows to return and whether the request was clamped to the maximum. 0
+    // asks for as many as allowed.
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid limit {:?}, expected a non-negative integer", limit))?,
+        None => 100,
+    };
+    match limit {
+        0 => Ok((max, false)),
+        limit if limit > max => Ok((max, true)),
+        limit => Ok((limit, false)),
+    }
+}
+
+// OpenAPI descriptions of the window parameters history_query parses
+const HISTORY_FROM: &str = "Start of the window, RFC 3339 or YYYY-MM-DD HH:MM:SS UTC. Without from and to the window is the last PIDASH_HISTORY_DEFAULT_HOURS (24) hours, with only to it starts at the first row";
+const HISTORY_TO: &str = "End of the window, RFC 3339, YYYY-MM-DD HH:MM:SS UTC or `now`, `now` by default";
+const HISTORY_LIMIT: &str = "Maximum rows, newest first, 100 by default. 0 or anything above PIDASH_HISTORY_MAX_LIMIT returns that maximum";
+
+// History request parameters, validated and normalized to the stored format
+struct HistoryQuery {
+    from: String,
+    to: String,
+    limit: usize,
+    // Whether limit was lowered to PIDASH_HISTORY_MAX_LIMIT
+    clamped: bool,
+    // Bucket size in seconds
+    bucket: Option<i64>,
+    include_network: bool,
+    // Zone the returned timestamps are converted to, they are stored in UTC
+    timezone: chrono_tz::Tz,
+}
+
+fn history_window(config: &Config, params: &HashMap<String, String>) -> Result<(String, String), ApiError> {
+    // Extract from and to dates from query parameters. When both are omitted the
+    // default window ending now is used, otherwise a missing from means 1970-01-01T00:00:00Z
+    let window = config.history_default_hours;
+    let start = i64::try_from(window)
+        .ok()
+        .and_then(chrono::Duration::try_hours)
+        .and_then(|window| chrono::Utc::now().checked_sub_signed(window));
+    let first = match start {
+        // A window reaching past what chrono can represent covers all history
+        Some(start) if window > 0 && !params.contains_key("from") && !params.contains_key("to") => {
+            start.format(TIMESTAMP_FORMAT).to_string()
         }
+        _ => "1970-01-01T00:00:00Z".to_string(),
     };
-    trace!("Executing query with parameters: from: {}, to: {}, limit: {}", from, to, limit);
-
-    let rows_result = stmt.query_map(params![from, to, limit], |row| {
-        Ok(json!({
-            "cpu_usage": row.get::<_, f64>(0)?,
-            "mem_total": row.get::<_, i32>(1)?,
-            "mem_used": row.get::<_, i32>(2)?,
-            "disk_total": row.get::<_, i32>(3)?,
-            "disk_used": row.get::<_, i32>(4)?,
-            "disk_free": row.get::<_, i32>(5)?,
-            "timestamp": row.get::<_, String>(6)?,
-        }))
-    });
+    let last = "now".to_string();
+    let from = history_bound(params.get("from").unwrap_or(&first)).map_err(ApiError::bad_request)?;
+    let to = history_bound(params.get("to").unwrap_or(&last)).map_err(ApiError::bad_request)?;
+    Ok((from, to))
+}
+
+fn history_timezone(config: &Config, params: &HashMap<String, String>) -> Result<chrono_tz::Tz, ApiError> {
+    match params.get("tz") {
+        Some(tz) => tz
+            .parse::<chrono_tz::Tz>()
+            .map_err(|_| ApiError::bad_request(format!("Unknown timezone {:?}, expected e.g. Europe/Berlin", tz))),
+        None => Ok(config.timezone),
+    }
+}
+
+fn history_query(config: &Config, params: &HashMap<String, String>) -> Result<HistoryQuery, ApiError> {
+    let (from, to) = history_window(config, params)?;
+    let (limit, clamped) = history_limit(params, config.history_max_limit).map_err(ApiError::bad_request)?;
+    let bucket = match params.get("bucket") {
+        Some(bucket) => Some(parse_bucket(bucket).ok_or_else(|| {
+            ApiError::bad_request(format!("Invalid bucket {:?}, expected e.g. 30s, 5m, 1h or 1d", bucket))
+        })?),
+        None => None,
+    };
+    let timezone = history_timezone(config, params)?;
+    Ok(HistoryQuery {
+        from,
+        to,
+        limit,
+        clamped,
+        bucket,
+        include_network: params.get("include_network").map(String::as_str) == Some("true"),
+        timezone,
+    })
+}
 
-    trace!("Query executed, processing results");
-
-    match rows_result {
-        Ok(rows) => {
-            let mut values = Vec::new();
-            for row in rows {
-                match row {
-                    Ok(value) => values.push(value),
-                    Err(e) => {
-                        error!("Error processing row: {}", e);
-                        return Json(json!({
-                            "error": format!("Error processing row: {}", e)
-                        }));
+#[utoipa::path(
+    get,
+    path = "/history",
+    params(
+        ("from" = Option<String>, Query, description = HISTORY_FROM),
+        ("to" = Option<String>, Query, description = HISTORY_TO),
+        ("limit" = Option<usize>, Query, description = HISTORY_LIMIT),
+        ("bucket" = Option<String>, Query, description = "Average over buckets such as 5m or 1h"),
+        ("include_network" = Option<bool>, Query, description = "true adds the per-interface network counters logged with each row"),
+        ("tz" = Option<String>, Query, description = "IANA timezone for the returned timestamps, PIDASH_TZ by default"),
+    ),
+    responses(
+        (status = 200, description = "Logged rows or bucket averages, newest first", body = HistoryResponse),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 500, description = "Database query failed", body = crate::error::ErrorBody),
+    )
+)]
+async fn get_history(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> ApiResult<HistoryResponse> {
+    // Handle history requests with optional query parameters
+    trace!("Fetching history data with parameters: {:?}", params);
+    let query = history_query(&state.config, &params)?;
+    let (limit, clamped, timezone) = (query.limit, query.clamped, query.timezone);
+    let db = state.db.clone();
+    let mut data = tokio::task::spawn_blocking(move || query_history(&db, &query))
+        .await
+        .map_err(|e| ApiError::internal(format!("History query task failed: {}", e)))?
+        .map_err(ApiError::internal)?;
+    data.localize(timezone);
+    Ok(Json(HistoryResponse {
+        data,
+        timezone: timezone.name(),
+        limit_clamped_to: clamped.then_some(limit),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/history/latest",
+    responses(
+        (status = 200, description = "The newest logged row", body = HistoryRow),
+        (status = 404, description = "No history logged yet", body = crate::error::ErrorBody),
+        (status = 500, description = "Database query failed", body = crate::error::ErrorBody),
+    )
+)]
+async fn get_history_latest(State(state): State<AppState>) -> Result<Json<HistoryRow>, ApiError> {
+    // The most recently logged row on its own, 404 before the first one
+    trace!("Fetching latest history row for http request");
+    let db = state.db.clone();
+    let row = tokio::task::spawn_blocking(move || latest_history_row(&db))
+        .await
+        .map_err(|e| ApiError::internal(format!("History query task failed: {}", e)))?
+        .map_err(ApiError::internal)?;
+    let mut row = row.ok_or_else(|| ApiError::not_found("No history logged yet"))?;
+    if let Some(utc) = localize_timestamp(&row.timestamp, chrono_tz::UTC) {
+        row.timestamp = utc;
+    }
+    Ok(Json(row))
+}
+
+fn latest_history_row(db: &db::Db) -> Result<Option<HistoryRow>, String> {
+    let conn = db.open().map_err(|e| format!("Failed to open database: {}", e))?;
+    conn.query_row(
+        &format!("SELECT {} FROM 'values' ORDER BY timestamp DESC LIMIT 1", HISTORY_COLUMNS),
+        [],
+        history_row,
+    )
+    .optional()
+    .map_err(|e| format!("Query execution failed: {}", e))
+}
+
+impl HistoryData {
+    // Convert the stored UTC timestamps of history rows or buckets to RFC 3339
+    // times in `timezone`, e.g. 2024-01-01T13:00:00+01:00 or 2024-01-01T12:00:00Z.
+    // The stored format has no zone, which browsers parse as local time or not at all.
+    fn localize(&mut self, timezone: chrono_tz::Tz) {
+        let timestamps: Vec<&mut String> = match self {
+            HistoryData::Rows(rows) => rows.iter_mut().map(|row| &mut row.timestamp).collect(),
+            HistoryData::Buckets(buckets) => buckets.iter_mut().map(|bucket| &mut bucket.timestamp).collect(),
+        };
+        for timestamp in timestamps {
+            if let Some(local) = localize_timestamp(timestamp, timezone) {
+                *timestamp = local;
+            }
+        }
+    }
+}
+
+fn localize_timestamp(timestamp: &str, timezone: chrono_tz::Tz) -> Option<String> {
+    let utc = chrono::NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+    Some(utc.and_utc().with_timezone(&timezone).to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
+fn query_history(db: &db::Db, query: &HistoryQuery) -> Result<HistoryData, String> {
+    let conn = db.open().map_err(|e| format!("Failed to open database: {}", e))?;
+    if let Some(secs) = query.bucket {
+        return history_buckets(&conn, &query.from, &query.to, query.limit, secs).map(HistoryData::Buckets);
+    }
+    let mut rows = Vec::new();
+    each_history_row(&conn, query, |row| {
+        rows.push(row);
+        true
+    })?;
+    trace!("Successfully processed {} rows", rows.len());
+    if query.include_network {
+        network_history::attach(&conn, &mut rows)?;
+    }
+    Ok(HistoryData::Rows(rows))
+}
+
+fn each_history_row(conn: &rusqlite::Connection, query: &HistoryQuery, mut each: impl FnMut(HistoryRow) -> bool) -> Result<(), String> {
+    // Pass the rows of the query to `each` one at a time, newest first, until it
+    // returns false
+    trace!("Preparing to query history data from database with from: {}, to: {}, limit: {}", query.from, query.to, query.limit);
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM 'values' WHERE timestamp BETWEEN ? AND ? ORDER BY timestamp DESC LIMIT ?", HISTORY_COLUMNS))
+        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let rows = stmt
+        .query_map(params![query.from, query.to, query.limit], history_row)
+        .map_err(|e| format!("Query execution failed: {}", e))?;
+    for row in rows {
+        if !each(row.map_err(|e| format!("Error processing row: {}", e))?) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+
+    use super::*;
+
+    fn memory_state() -> AppState {
+        let mut config = Config::from_env();
+        config.db_path = ":memory:".to_string();
+        AppState::new(config)
+    }
+
+    fn insert_sample(state: &AppState, cpu_usage: f64, timestamp: &str) {
+        state
+            .db
+            .open()
+            .unwrap()
+            .execute(
+                "INSERT INTO 'values' (cpu_usage, mem_total, mem_used, disk_total, disk_used, disk_free, timestamp) VALUES (?, 1000, 500, 2000, 1500, 500, ?)",
+                params![cpu_usage, timestamp],
+            )
+            .unwrap();
+    }
+
+    fn history(state: &AppState, query: &[(&str, &str)]) -> Vec<Value> {
+        let params = query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let Ok(Json(body)) = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(get_history(State(state.clone()), Query(params)))
+        else {
+            panic!("history returned an error");
+        };
+        serde_json::to_value(body).unwrap()["data"].as_array().expect("history returned an error").clone()
+    }
+
+    #[test]
+    fn value_logging_writes_a_row() {
+        let state = memory_state();
+        value_logging(&state);
+        let data = history(&state, &[]);
+        assert_eq!(data.len(), 1);
+        assert!(data[0]["mem_total"].as_i64().unwrap() > 0);
+    }
+
+    #[test]
+    fn logging_leaves_shared_collectors_alone() {
+        // /network rates and cpu_usage's status stay with their own clients
+        let state = memory_state();
+        let sample = value_logging(&state);
+        assert!(sample["cpu_usage"]["cpu_usage"].is_number());
+        assert!(state.collectors.status("network").last_success.is_none());
+        assert!(state.collectors.status("cpu_usage").last_success.is_none());
+    }
+
+    #[test]
+    fn history_converts_timezone() {
+        let state = memory_state();
+        insert_sample(&state, 1.0, "2024-07-01 12:00:00");
+        let params = [("from", "2024-01-01"), ("tz", "Europe/Berlin")];
+        let params: HashMap<String, String> = params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let query = history_query(&state.config, &params).unwrap();
+        let mut data = query_history(&state.db, &query).unwrap();
+        data.localize(query.timezone);
+        assert_eq!(serde_json::to_value(data).unwrap()[0]["timestamp"], "2024-07-01T14:00:00+02:00");
+        let mut data = query_history(&state.db, &query).unwrap();
+        data.localize(chrono_tz::UTC);
+        assert_eq!(serde_json::to_value(data).unwrap()[0]["timestamp"], "2024-07-01T12:00:00Z");
+        let params = HashMap::from([("tz".to_string(), "Mars/Olympus".to_string())]);
+        assert!(history_query(&state.config, &params).is_err());
+    }
+
+    #[test]
+    fn oversized_default_window_covers_all_history() {
+        let mut config = Config::from_env();
+        for hours in [u64::MAX, i64::MAX as u64, 1 << 40] {
+            config.history_default_hours = hours;
+            let (from, _) = history_window(&config, &HashMap::new()).unwrap();
+            assert!(from.starts_with("1970-01-01"), "{} hours gave {}", hours, from);
+        }
+    }
+
+    #[test]
+    fn latest_history_row_is_newest() {
+        let state = memory_state();
+        assert!(latest_history_row(&state.db).unwrap().is_none());
+        insert_sample(&state, 1.0, "2024-01-01 00:00:00");
+        insert_sample(&state, 2.0, "2024-01-02 00:00:00");
+        insert_sample(&state, 3.0, "2023-12-31 00:00:00");
+        assert_eq!(latest_history_row(&state.db).unwrap().unwrap().cpu_usage, 2.0);
+    }
+
+    #[test]
+    fn refused_rows_are_written_next_cycle() {
+        let state = memory_state();
+        state.db.open().unwrap().execute("DROP TABLE 'values'", ()).unwrap();
+        value_logging(&state);
+        assert_eq!(state.pending_rows.lock().unwrap().len(), 1);
+        db::create_schema(&state.db.open().unwrap());
+        value_logging(&state);
+        assert!(state.pending_rows.lock().unwrap().is_empty());
+        assert_eq!(history(&state, &[]).len(), 2);
+    }
+
+    #[test]
+    fn history_includes_network_on_request() {
+        let state = memory_state();
+        value_logging(&state);
+        assert!(history(&state, &[])[0].get("network").is_none());
+        let data = history(&state, &[("include_network", "true")]);
+        assert!(data[0]["network"].is_array());
+    }
+
+    #[test]
+    fn cpu_cache_is_free_while_logging_samples() {
+        let state = memory_state();
+        let logger = {
+            let state = state.clone();
+            std::thread::spawn(move || value_logging(&state))
+        };
+        // The first cycle has no interval delta and falls back to a sample
+        std::thread::sleep(CPU_SAMPLE_INTERVAL / 4);
+        let started = std::time::Instant::now();
+        drop(state.cpu_cache.lock().unwrap());
+        assert!(started.elapsed() < CPU_SAMPLE_INTERVAL / 2);
+        logger.join().unwrap();
+        assert!(state.cpu_cache.lock().unwrap().usage.is_some());
+    }
+
+    #[test]
+    fn network_counters_stay_with_their_row() {
+        let state = memory_state();
+        let conn = state.db.open().unwrap();
+        for rx_bytes in [100, 200] {
+            let network = json!([{"interface": "eth0", "rx_bytes": rx_bytes, "tx_bytes": 1}]);
+            let row = HistoryRow {
+                cpu_usage: 1.0,
+                mem_total: 1000,
+                mem_used: 500,
+                disk_total: None,
+                disk_used: None,
+                disk_free: None,
+                anomaly: false,
+                cpu_temp: None,
+                fan_speed: None,
+                timestamp: "2024-01-01 00:00:00".to_string(),
+                id: None,
+                network: Some(network_history::counters(&network)),
+            };
+            insert_row(&conn, &row).unwrap();
+        }
+        drop(conn);
+        let data = history(&state, &[("from", "2024-01-01 00:00:00"), ("include_network", "true")]);
+        let mut rx_bytes: Vec<_> = data.iter().map(|row| row["network"][0]["rx_bytes"].as_i64().unwrap()).collect();
+        rx_bytes.sort();
+        assert_eq!(rx_bytes, [100, 200]);
+        assert!(data.iter().all(|row| row["network"].as_array().unwrap().len() == 1));
+    }
+
+    #[test]
+    fn concurrent_history_reads_while_logging() {
+        let dir = crate::testing::temp_dir();
+        let path = dir.path().join("history.db");
+        let mut config = Config::from_env();
+        config.db_path = path.to_string_lossy().into_owned();
+        let state = AppState::new(config);
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+        let errors = runtime.block_on(async {
+            let writer = {
+                let state = state.clone();
+                tokio::task::spawn_blocking(move || {
+                    value_logging(&state);
+                    for i in 0..200 {
+                        insert_sample(&state, i as f64, "2024-01-01 00:00:00");
                     }
-                }
+                    value_logging(&state);
+                })
+            };
+            let readers: Vec<_> = (0..50)
+                .map(|_| {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        let params = HashMap::from([("from".to_string(), "2000-01-01 00:00:00".to_string())]);
+                        get_history(State(state), Query(params)).await.err().map(|e| e.message().to_string())
+                    })
+                })
+                .collect();
+            writer.await.unwrap();
+            let mut errors = Vec::new();
+            for reader in readers {
+                errors.extend(reader.await.unwrap());
             }
-            trace!("Successfully processed {} rows", values.len());
-            Json(json!({ "data": values }))
+            errors
+        });
+        assert!(errors.is_empty(), "history requests failed: {:?}", errors);
+    }
+
+    #[test]
+    fn memory_databases_are_separate() {
+        let first = memory_state();
+        let second = memory_state();
+        insert_sample(&first, 10.0, "2024-01-01 00:00:00");
+        assert_eq!(history(&first, &[("from", "2000-01-01 00:00:00")]).len(), 1);
+        assert!(history(&second, &[("from", "2000-01-01 00:00:00")]).is_empty());
+    }
+
+    #[test]
+    fn history_filters_by_range_newest_first() {
+        let state = memory_state();
+        insert_sample(&state, 10.0, "2024-01-01 00:00:00");
+        insert_sample(&state, 30.0, "2024-01-03 00:00:00");
+        insert_sample(&state, 20.0, "2024-01-02 00:00:00");
+        insert_sample(&state, 40.0, "2024-01-04 00:00:00");
+        let data = history(&state, &[("from", "2024-01-02 00:00:00"), ("to", "2024-01-03 12:00:00")]);
+        let cpu: Vec<f64> = data.iter().map(|row| row["cpu_usage"].as_f64().unwrap()).collect();
+        assert_eq!(cpu, vec![30.0, 20.0]);
+    }
+
+    #[test]
+    fn history_applies_limit() {
+        let state = memory_state();
+        for day in 1..=5 {
+            insert_sample(&state, day as f64, &format!("2024-01-0{} 00:00:00", day));
         }
-        Err(e) => {
-            error!("Query execution failed: {}", e);
-            Json(json!({
-            "error": format!("Query execution failed: {}", e)
-        }))
-        },
+        let data = history(&state, &[("from", "2024-01-01 00:00:00"), ("limit", "2")]);
+        let cpu: Vec<f64> = data.iter().map(|row| row["cpu_usage"].as_f64().unwrap()).collect();
+        assert_eq!(cpu, vec![5.0, 4.0]);
+    }
+
+    #[test]
+    fn ipv6_listener_accepts_ipv4_clients() {
+        // Skipped where the host has IPv6 disabled
+        let Ok(listener) = listener("[::]:0".parse().unwrap()) else {
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer.ip().to_canonical(), std::net::Ipv4Addr::LOCALHOST);
+    }
+
+    #[test]
+    fn disk_usage_errors_instead_of_panicking() {
+        let (total, used, free) = disk_usage("/").unwrap();
+        assert!(total > 0 && used <= total && free <= total);
+        assert!(disk_usage("/no/such/mount").is_err());
+    }
+
+    #[test]
+    fn history_averages_buckets() {
+        let state = memory_state();
+        insert_sample(&state, 10.0, "2024-01-01 10:05:00");
+        insert_sample(&state, 20.0, "2024-01-01 10:55:00");
+        insert_sample(&state, 40.0, "2024-01-01 12:30:00");
+        let data = history(&state, &[("from", "2024-01-01 00:00:00"), ("bucket", "1h")]);
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0]["timestamp"], "2024-01-01T12:00:00Z");
+        assert_eq!(data[0]["count"], 1);
+        assert_eq!(data[1]["timestamp"], "2024-01-01T10:00:00Z");
+        assert_eq!(data[1]["cpu_usage"], 15.0);
+        assert_eq!(data[1]["count"], 2);
+        assert_eq!(parse_bucket("5m"), Some(300));
+        assert_eq!(parse_bucket("0h"), None);
+        assert_eq!(parse_bucket("1w"), None);
+    }
+
+    #[test]
+    fn history_accepts_iso_8601_bounds() {
+        let state = memory_state();
+        insert_sample(&state, 10.0, "2024-01-01 23:30:00");
+        let data = history(&state, &[("from", "2024-01-01T00:00:00Z"), ("to", "2024-01-02T00:00:00Z")]);
+        assert_eq!(data.len(), 1);
+        // 01:00 at UTC+2 is 23:00 UTC the day before
+        let data = history(&state, &[("from", "2024-01-02T01:00:00+02:00"), ("to", "2024-01-02T01:45:00+02:00")]);
+        assert_eq!(data.len(), 1);
+        assert_eq!(history_bound("2024-01-02").unwrap(), "2024-01-02 00:00:00");
+        assert!(history_bound("yesterday").is_err());
+    }
+
+    #[test]
+    fn history_limit_is_validated_and_clamped() {
+        let params = |limit: &str| HashMap::from([("limit".to_string(), limit.to_string())]);
+        assert_eq!(history_limit(&HashMap::new(), 10000).unwrap(), (100, false));
+        assert_eq!(history_limit(&params("50"), 10000).unwrap(), (50, false));
+        assert_eq!(history_limit(&params("0"), 10000).unwrap(), (10000, false));
+        assert_eq!(history_limit(&params("999999999"), 10000).unwrap(), (10000, true));
+        assert!(history_limit(&params("lots"), 10000).is_err());
+        assert!(history_limit(&params("-1"), 10000).is_err());
+    }
+
+    #[test]
+    fn history_rejects_bad_parameters_with_400() {
+        let state = memory_state();
+        let params = HashMap::from([("limit".to_string(), "lots".to_string())]);
+        let error = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(get_history(State(state), Query(params)))
+            .err()
+            .unwrap();
+        assert_eq!(error.into_response().status(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn parses_proc_stat() {
+        let parse_cpu_times = |stat: &str| parse_cpu_fields(stat).map(|fields| total_and_idle(&fields));
+        let stat = "cpu  100 20 30 800 10 5 5 0 0 0\ncpu0 50 10 15 400 5 2 3 0 0 0\nintr 12345\n";
+        assert_eq!(parse_cpu_times(stat), Some((970.0, 800.0)));
+        assert_eq!(parse_cpu_times("cpu  1 2 3\n"), None);
+        assert_eq!(parse_cpu_times("intr 1\n"), None);
+        // Steal counts towards the total, guest is already part of user
+        assert_eq!(parse_cpu_times("cpu  100 0 0 800 0 0 0 100 50 0\n"), Some((1000.0, 800.0)));
+        let breakdown = breakdown_between(&[0.0; 8], &[40.0, 0.0, 10.0, 40.0, 0.0, 0.0, 0.0, 10.0]).unwrap();
+        assert_eq!(breakdown["steal"], 10.0);
+        assert_eq!(breakdown["idle"], 40.0);
+        // 90 of 100 new jiffies were busy
+        assert_eq!(usage_between((970.0, 800.0), (1070.0, 810.0)), Some(90.0));
+        assert_eq!(usage_between((970.0, 800.0), (970.0, 800.0)), None);
+    }
+
+    #[test]
+    fn parses_proc_meminfo() {
+        let meminfo = "MemTotal:        3884096 kB\nMemFree:          512000 kB\nMemAvailable:    2884096 kB\nSwapTotal:        102396 kB\nSwapFree:          51198 kB\n";
+        assert_eq!(meminfo_kb(meminfo, "MemTotal:"), Some(3884096));
+        assert_eq!(meminfo_kb(meminfo, "MemAvailable:"), Some(2884096));
+        assert_eq!(meminfo_kb(meminfo, "SwapFree:"), Some(51198));
+        assert_eq!(meminfo_kb(meminfo, "Hugetlb:"), None);
+    }
+
+    #[tokio::test]
+    async fn compresses_json_but_not_event_streams() {
+        use axum::body::Body;
+        use axum::response::sse::{Event, Sse};
+        use tower::ServiceExt;
+
+        let app = Router::new()
+            .route("/history", get(|| async { Json(json!({"history": vec![json!({"cpu_usage": 12.5}); 500]})) }))
+            .route(
+                "/stream",
+                get(|| async {
+                    let events = futures_util::stream::iter([Ok::<_, std::convert::Infallible>(Event::default().data("{}"))]);
+                    Sse::new(events)
+                }),
+            )
+            .layer(CompressionLayer::new());
+        let encoding = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = http::Request::get(path)
+                    .header(http::header::ACCEPT_ENCODING, "gzip, br")
+                    .body(Body::empty())
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                response.headers().get(http::header::CONTENT_ENCODING).map(|v| v.to_str().unwrap().to_string())
+            }
+        };
+        assert_eq!(encoding("/history").await.as_deref(), Some("br"));
+        assert_eq!(encoding("/stream").await, None);
     }
 }
@@ -0,0 +1,85 @@
+use axum::Json;
+use log::{error, trace};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::collector::percent;
+use crate::error::{ApiError, ApiResult};
+use crate::meminfo_kb;
+
+#[utoipa::path(
+    get,
+    path = "/mem_usage/detailed",
+    responses(
+        (status = 200, description = "Memory breakdown as reported by free", body = MemBreakdown),
+        (status = 500, description = "Reading failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_mem_detailed() -> ApiResult<MemBreakdown> {
+    // The used, buff/cache and free split `free` shows, in kilobytes. Cache can
+    // be reclaimed under pressure, so a large buff/cache is not a problem the
+    // way a large used is.
+    trace!("Reading detailed memory usage for http request");
+    let meminfo = tokio::fs::read_to_string("/proc/meminfo").await.map_err(|e| {
+        error!("Failed to read /proc/meminfo: {}", e);
+        ApiError::internal("Failed to read memory usage")
+    })?;
+    Ok(Json(mem_breakdown(&meminfo).map_err(ApiError::internal)?))
+}
+
+// Sizes in kilobytes
+#[derive(Serialize, ToSchema)]
+pub struct MemBreakdown {
+    total: u64,
+    used: u64,
+    free: u64,
+    shared: u64,
+    buffers: u64,
+    cached: u64,
+    buff_cache: u64,
+    available: u64,
+    used_percent: i32,
+    buff_cache_percent: i32,
+}
+
+fn mem_breakdown(meminfo: &str) -> Result<MemBreakdown, String> {
+    let field = |key: &str| meminfo_kb(meminfo, key).ok_or_else(|| format!("Failed to parse {} from /proc/meminfo", key));
+    let total = field("MemTotal:")?;
+    let free = field("MemFree:")?;
+    let available = field("MemAvailable:")?;
+    let buffers = field("Buffers:")?;
+    // free counts reclaimable slab as cache too
+    let cached = field("Cached:")? + field("SReclaimable:")?;
+    let shared = field("Shmem:")?;
+    let buff_cache = buffers + cached;
+    // Same as /mem_usage and current free: memory that can't be reclaimed
+    let used = total.saturating_sub(available);
+    Ok(MemBreakdown {
+        total,
+        used,
+        free,
+        shared,
+        buffers,
+        cached,
+        buff_cache,
+        available,
+        used_percent: percent(used, total),
+        buff_cache_percent: percent(buff_cache, total),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_used_cache_and_free() {
+        let meminfo = "MemTotal:        1000 kB\nMemFree:          200 kB\nMemAvailable:     600 kB\nBuffers:           50 kB\nCached:           300 kB\nSwapCached:         5 kB\nShmem:             20 kB\nSReclaimable:      30 kB\n";
+        let value = mem_breakdown(meminfo).unwrap();
+        assert_eq!(value.buff_cache, 380);
+        assert_eq!(value.cached, 330);
+        assert_eq!(value.used, 400);
+        assert_eq!(value.used_percent, 40);
+        assert!(mem_breakdown("MemTotal: 1000 kB\n").is_err());
+    }
+}
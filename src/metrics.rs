@@ -0,0 +1,198 @@
+use std::fmt::Write;
+
+use axum::extract::State;
+use axum::response::IntoResponse;
+use http::header;
+use log::{error, trace};
+use serde_json::Value;
+
+use crate::AppState;
+
+// Numeric fields of a collector, None when it is unsupported or failed
+type Fields = Option<Vec<(String, f64)>>;
+
+// Numeric fields of every supported collector that could be read, by collector name
+async fn collect_numeric(state: &AppState) -> Vec<(&'static str, Fields)> {
+    // Collectors may block, cpu_usage sleeps between its two samples
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || read_numeric(&state))
+        .await
+        .unwrap_or_else(|e| {
+            error!("Collector task failed: {}", e);
+            Vec::new()
+        })
+}
+
+fn read_numeric(state: &AppState) -> Vec<(&'static str, Fields)> {
+    state
+        .collectors
+        .iter()
+        .map(|collector| {
+            let name = collector.name();
+            let value = if collector.supported() {
+                state.collectors.collect(collector).inspect_err(|e| error!("Collector {} failed: {}", name, e)).ok()
+            } else {
+                None
+            };
+            (name, value.map(numeric_fields))
+        })
+        .collect()
+}
+
+// Numeric fields of one collector's value. Numbers kept as strings for
+// compatibility are included, booleans as 0/1.
+pub fn numeric_fields(value: Value) -> Vec<(String, f64)> {
+    match value {
+        Value::Object(fields) => fields
+            .into_iter()
+            .filter_map(|(key, field)| {
+                let number = match &field {
+                    Value::Number(n) => n.as_f64(),
+                    Value::Bool(b) => Some(*b as u8 as f64),
+                    Value::String(s) => s.parse::<f64>().ok(),
+                    _ => None,
+                }?;
+                Some((key, number))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Collector fields exported under conventional Prometheus names and base
+// units: collector, field, metric name, help text and scale to the base unit
+const NAMED_METRICS: [(&str, &str, &str, &str, f64); 11] = [
+    ("cpu_temp", "cpu_temp", "pidash_cpu_temperature_celsius", "CPU temperature", 1.0),
+    ("fan_speed", "fan_speed", "pidash_fan_speed_rpm", "Fan speed in revolutions per minute", 1.0),
+    ("uptime", "uptime", "pidash_uptime_seconds", "Time since boot", 0.001),
+    ("mem_usage", "mem_total", "pidash_memory_total_bytes", "Total memory", 1024.0),
+    ("mem_usage", "mem_used", "pidash_memory_used_bytes", "Memory in use", 1024.0),
+    ("swap_usage", "swap_total", "pidash_swap_total_bytes", "Total swap space", 1024.0),
+    ("swap_usage", "swap_used", "pidash_swap_used_bytes", "Swap space in use", 1024.0),
+    ("disk_usage", "total", "pidash_disk_total_bytes", "Size of the root filesystem", 1.0),
+    ("disk_usage", "used", "pidash_disk_used_bytes", "Space used on the root filesystem", 1.0),
+    ("disk_usage", "free", "pidash_disk_free_bytes", "Space available on the root filesystem", 1.0),
+    ("cpu_usage", "cpu_usage", "pidash_cpu_usage_percent", "CPU usage over the last sample interval", 1.0),
+];
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Every collector in the Prometheus text format", content_type = "text/plain", body = String),
+    )
+)]
+pub async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    // Render every collector in the Prometheus text format. Well known fields
+    // use the names in NAMED_METRICS, any other numeric field becomes a gauge
+    // named pidash_<collector>_<field>. Fields that can't be read are left out.
+    trace!("Rendering metrics for http request");
+    let collected = collect_numeric(&state).await;
+    let mut out = String::new();
+    // All samples of a metric have to follow its HELP and TYPE lines
+    let _ = writeln!(out, "# HELP pidash_collector_up Whether the collector could be read");
+    let _ = writeln!(out, "# TYPE pidash_collector_up gauge");
+    for (name, fields) in &collected {
+        let _ = writeln!(out, "pidash_collector_up{{collector=\"{}\"}} {}", name, fields.is_some() as u8);
+    }
+    for (name, fields) in collected {
+        for (key, number) in fields.unwrap_or_default() {
+            if !number.is_finite() {
+                continue;
+            }
+            let named = NAMED_METRICS
+                .iter()
+                .find(|(collector, field, ..)| *collector == name && *field == key);
+            let (metric, help, number) = match named {
+                Some((_, _, metric, help, scale)) => (metric.to_string(), help.to_string(), number * scale),
+                None => (format!("pidash_{}_{}", name, key), format!("{} reported by the {} collector", key, name), number),
+            };
+            let _ = writeln!(out, "# HELP {} {}", metric, help);
+            let _ = writeln!(out, "# TYPE {} gauge", metric);
+            let _ = writeln!(out, "{} {}", metric, number);
+        }
+    }
+    let latency = state.latency.stats();
+    let _ = writeln!(out, "# HELP pidash_http_request_duration_seconds Time to handle a request, quantiles over the last requests");
+    let _ = writeln!(out, "# TYPE pidash_http_request_duration_seconds summary");
+    for (quantile, duration) in [("0.5", latency.p50), ("0.95", latency.p95)] {
+        let _ = writeln!(out, "pidash_http_request_duration_seconds{{quantile=\"{}\"}} {}", quantile, duration.as_secs_f64());
+    }
+    let _ = writeln!(out, "pidash_http_request_duration_seconds_sum {}", latency.sum.as_secs_f64());
+    let _ = writeln!(out, "pidash_http_request_duration_seconds_count {}", latency.count);
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics/influx",
+    responses(
+        (status = 200, description = "Every collector in the InfluxDB line protocol", content_type = "text/plain", body = String),
+    )
+)]
+pub async fn get_influx(State(state): State<AppState>) -> impl IntoResponse {
+    // Render every collector as one InfluxDB line protocol point, measured by
+    // collector name and tagged with the host and board serial
+    trace!("Rendering influx metrics for http request");
+    let tags = influx_tags(&state).await;
+    let timestamp = chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0);
+    let collected = collect_numeric(&state).await.into_iter().map(|(name, fields)| (name, fields.unwrap_or_default()));
+    let out = influx_lines(&tags, collected, timestamp);
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], out)
+}
+
+// Host and serial tags shared by every point, with their leading commas
+pub async fn influx_tags(state: &AppState) -> String {
+    let hostname = tokio::fs::read_to_string("/proc/sys/kernel/hostname")
+        .await
+        .map(|h| h.trim().to_string())
+        .unwrap_or_default();
+    let mut tags = String::new();
+    if !hostname.is_empty() {
+        let _ = write!(tags, ",host={}", escape_tag(&hostname));
+    }
+    if let Some(serial) = &state.system_info.serial {
+        let _ = write!(tags, ",serial={}", escape_tag(serial));
+    }
+    tags
+}
+
+// One line-protocol point per collector, measured as the collector name
+pub fn influx_lines<'a>(
+    tags: &str,
+    collected: impl IntoIterator<Item = (&'a str, Vec<(String, f64)>)>,
+    timestamp: i64,
+) -> String {
+    let mut out = String::new();
+    for (name, fields) in collected {
+        // A point needs at least one field
+        if fields.is_empty() {
+            continue;
+        }
+        let fields = fields
+            .iter()
+            .map(|(key, number)| format!("{}={}", escape_tag(key), number))
+            .collect::<Vec<String>>()
+            .join(",");
+        let _ = writeln!(out, "{}{} {} {}", name, tags, fields, timestamp);
+    }
+    out
+}
+
+fn escape_tag(value: &str) -> String {
+    // Tag keys, tag values and field keys escape commas, equals signs and spaces
+    value.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renders_line_protocol() {
+        let fields = numeric_fields(json!({"cpu usage": 12.5, "throttled": true, "serial": "n/a", "mem_total": "1024"}));
+        let out = influx_lines(",host=pi", [("cpu", fields), ("empty", Vec::new())], 42);
+        assert_eq!(out, "cpu,host=pi cpu\\ usage=12.5,mem_total=1024,throttled=1 42\n");
+    }
+}
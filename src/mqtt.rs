@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{error, info, trace};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{Value, json};
+
+use crate::AppState;
+use crate::metrics;
+
+// Messages queued for the broker, a full queue drops new messages
+const QUEUE_CAPACITY: usize = 256;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+// Wait between connection attempts while the broker is unreachable
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+// Home Assistant unit and device class of well known fields: collector,
+// field, unit and device class. Other numeric fields are announced without.
+const SENSOR_UNITS: [(&str, &str, &str, Option<&str>); 14] = [
+    ("cpu_temp", "cpu_temp", "°C", Some("temperature")),
+    ("cpu_usage", "cpu_usage", "%", None),
+    ("fan_speed", "fan_speed", "RPM", None),
+    ("uptime", "uptime_seconds", "s", Some("duration")),
+    ("mem_usage", "mem_percent", "%", None),
+    ("mem_usage", "mem_total", "kB", Some("data_size")),
+    ("mem_usage", "mem_used", "kB", Some("data_size")),
+    ("swap_usage", "swap_percent", "%", None),
+    ("disk_usage", "percent", "%", None),
+    ("disk_usage", "inodes_percent", "%", None),
+    ("disk_usage", "total", "B", Some("data_size")),
+    ("disk_usage", "used", "B", Some("data_size")),
+    ("disk_usage", "free", "B", Some("data_size")),
+    ("cpu_freq", "average_mhz", "MHz", Some("frequency")),
+];
+
+// Publishes each logged sample to an MQTT broker, one topic per collector
+pub struct Publisher {
+    client: AsyncClient,
+    topic: String,
+    discovery_prefix: String,
+    // Node id of the Home Assistant device, unique per board
+    node_id: String,
+    // Sensors whose discovery config was sent since the last connect
+    announced: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Publisher {
+    // None unless PIDASH_MQTT_HOST is set. Connecting happens in the
+    // background and is retried for as long as the broker is unreachable.
+    pub fn new(state: &AppState) -> Option<Publisher> {
+        let config = &state.config;
+        let host = config.mqtt_host.clone()?;
+        let node_id = node_id(&config.mqtt_topic, state.system_info.serial.as_deref());
+        let status_topic = format!("{}/status", config.mqtt_topic);
+        let mut options = MqttOptions::new(format!("pidash-{}", node_id), host.clone(), config.mqtt_port);
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_last_will(LastWill::new(&status_topic, "offline", QoS::AtLeastOnce, true));
+        if let Some(username) = &config.mqtt_username {
+            options.set_credentials(username, config.mqtt_password.clone().unwrap_or_default());
+        }
+        let (client, mut eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+        let announced = Arc::new(Mutex::new(HashSet::new()));
+        let publisher = Publisher {
+            client: client.clone(),
+            topic: config.mqtt_topic.clone(),
+            discovery_prefix: config.mqtt_discovery_prefix.clone(),
+            node_id,
+            announced: announced.clone(),
+        };
+        info!("Publishing history samples to MQTT broker {}:{}", host, config.mqtt_port);
+        tokio::spawn(async move {
+            let mut failing = false;
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to MQTT broker");
+                        failing = false;
+                        // A restarted broker may have lost retained configs
+                        announced.lock().unwrap().clear();
+                        if let Err(e) = client.try_publish(&status_topic, QoS::AtLeastOnce, true, "online") {
+                            error!("Failed to queue MQTT status: {}", e);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // Log once per outage, not on every retry
+                        if !failing {
+                            error!("MQTT connection failed, retrying every {}s: {}", RECONNECT_DELAY.as_secs(), e);
+                            failing = true;
+                        }
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+        Some(publisher)
+    }
+
+    // Publish every collector's value as JSON to <topic>/<collector>, after
+    // announcing its numeric fields to Home Assistant
+    pub fn publish(&self, state: &AppState, sample: &HashMap<&'static str, Value>) {
+        let mut failed = 0;
+        let mut last_error = None;
+        for collector in state.collectors.iter() {
+            let name = collector.name();
+            let Some(value) = sample.get(name) else {
+                continue;
+            };
+            if !self.discovery_prefix.is_empty() {
+                for (field, _) in metrics::numeric_fields(value.clone()) {
+                    let key = format!("{}/{}", name, field);
+                    if !self.announced.lock().unwrap().insert(key.clone()) {
+                        continue;
+                    }
+                    if let Err(e) = self.announce(state, name, &field) {
+                        // Announce again on the next sample
+                        self.announced.lock().unwrap().remove(&key);
+                        failed += 1;
+                        last_error = Some(e);
+                    }
+                }
+            }
+            let topic = format!("{}/{}", self.topic, name);
+            if let Err(e) = self.client.try_publish(topic, QoS::AtLeastOnce, false, value.to_string()) {
+                failed += 1;
+                last_error = Some(e.to_string());
+            }
+        }
+        match last_error {
+            Some(e) => error!("Failed to queue {} MQTT messages: {}", failed, e),
+            None => trace!("Queued sample for MQTT broker"),
+        }
+    }
+
+    fn announce(&self, state: &AppState, collector: &str, field: &str) -> Result<(), String> {
+        // Home Assistant MQTT discovery config of one sensor, retained so it
+        // survives Home Assistant restarts
+        let object_id = format!("{}_{}", collector, field);
+        let name = if collector == field { field.to_string() } else { format!("{} {}", collector, field) };
+        let mut config = json!({
+            "name": name,
+            "unique_id": format!("{}_{}", self.node_id, object_id),
+            "state_topic": format!("{}/{}", self.topic, collector),
+            "value_template": format!("{{{{ value_json.{} }}}}", field),
+            "state_class": "measurement",
+            "availability_topic": format!("{}/status", self.topic),
+            "device": {
+                "identifiers": [self.node_id],
+                "name": self.node_id,
+                "manufacturer": "Raspberry Pi",
+                "sw_version": env!("CARGO_PKG_VERSION")
+            }
+        });
+        if let Some(model) = &state.system_info.model {
+            config["device"]["model"] = json!(model);
+        }
+        if let Some((_, _, unit, class)) = SENSOR_UNITS.iter().find(|(c, f, _, _)| *c == collector && *f == field) {
+            config["unit_of_measurement"] = json!(unit);
+            if let Some(class) = class {
+                config["device_class"] = json!(class);
+            }
+        }
+        let topic = format!("{}/sensor/{}/{}/config", self.discovery_prefix, self.node_id, object_id);
+        self.client
+            .try_publish(topic, QoS::AtLeastOnce, true, config.to_string())
+            .map_err(|e| e.to_string())
+    }
+}
+
+// The board serial when known, so boards sharing a topic prefix stay apart,
+// the topic prefix otherwise. Only characters Home Assistant allows in ids.
+fn node_id(topic: &str, serial: Option<&str>) -> String {
+    let id = match serial {
+        Some(serial) => format!("pidash_{}", serial),
+        None => topic.to_string(),
+    };
+    id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_id_is_discovery_safe() {
+        assert_eq!(node_id("pidash", Some("10000000abcdef")), "pidash_10000000abcdef");
+        assert_eq!(node_id("home/pi 4", None), "home_pi_4");
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use axum::Json;
+use log::{error, trace};
+use nix::ifaddrs::getifaddrs;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+// RTF_UP | RTF_GATEWAY in the route flags
+const ROUTE_UP_GATEWAY: u32 = 0x0003;
+// Network interfaces, one directory each
+const NET_SYSFS: &str = "/sys/class/net";
+// IFF_UP and IFF_LOOPBACK in an interface's flags file
+const IFF_UP: u32 = 0x1;
+const IFF_LOOPBACK: u32 = 0x8;
+
+#[derive(Serialize, ToSchema)]
+pub struct Route {
+    interface: String,
+    #[schema(value_type = String)]
+    gateway: IpAddr,
+    metric: u32,
+}
+
+#[utoipa::path(
+    get,
+    path = "/network/config",
+    responses(
+        (status = 200, description = "Addresses, default routes and DNS servers", body = NetworkConfig),
+    )
+)]
+pub async fn get_network_config() -> Json<Option<NetworkConfig>> {
+    // Report the default routes, DNS servers and the addresses of the interface
+    // carrying the preferred default route
+    trace!("Fetching network configuration for http request");
+    // Reading /proc and the interface list blocks
+    let config = tokio::task::spawn_blocking(read_network_config)
+        .await
+        .inspect_err(|e| error!("Network config task failed: {}", e))
+        .ok();
+    Json(config)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NetworkConfig {
+    default_routes: Vec<Route>,
+    // Gateway and addresses of the preferred default route's interface
+    gateway: Option<String>,
+    dns_servers: Vec<String>,
+    interface: Option<String>,
+    addresses: Vec<String>,
+}
+
+fn read_network_config() -> NetworkConfig {
+    let mut routes = ipv4_default_routes();
+    routes.extend(ipv6_default_routes());
+    // The kernel prefers the lowest metric, IPv4 first when they tie
+    routes.sort_by_key(|route| (route.metric, route.gateway.is_ipv6()));
+    let primary = routes.first().map(|route| route.interface.clone());
+    let addresses = match &primary {
+        Some(interface) => interface_addresses(interface),
+        None => Vec::new(),
+    };
+    NetworkConfig {
+        gateway: routes.first().map(|route| route.gateway.to_string()),
+        default_routes: routes,
+        dns_servers: dns_servers(),
+        interface: primary,
+        addresses,
+    }
+}
+
+fn ipv4_default_routes() -> Vec<Route> {
+    // /proc/net/route fields are hex, addresses in host (little endian) byte order
+    let table = match std::fs::read_to_string("/proc/net/route") {
+        Ok(table) => table,
+        Err(e) => {
+            error!("Failed to read /proc/net/route: {}", e);
+            return Vec::new();
+        }
+    };
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let destination = u32::from_str_radix(fields.get(1)?, 16).ok()?;
+            let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+            let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+            if destination != 0 || flags & ROUTE_UP_GATEWAY != ROUTE_UP_GATEWAY {
+                return None;
+            }
+            Some(Route {
+                interface: fields[0].to_string(),
+                gateway: IpAddr::V4(Ipv4Addr::from(gateway.swap_bytes())),
+                metric: fields.get(6)?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+fn ipv6_default_routes() -> Vec<Route> {
+    // Fields: destination, prefix length, source, source prefix, next hop,
+    // metric, refcount, use, flags, interface. Missing without IPv6 support.
+    let Ok(table) = std::fs::read_to_string("/proc/net/ipv6_route") else {
+        return Vec::new();
+    };
+    table
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let destination = u128::from_str_radix(fields.first()?, 16).ok()?;
+            let prefix = u8::from_str_radix(fields.get(1)?, 16).ok()?;
+            let gateway = u128::from_str_radix(fields.get(4)?, 16).ok()?;
+            let flags = u32::from_str_radix(fields.get(8)?, 16).ok()?;
+            if destination != 0 || prefix != 0 || flags & ROUTE_UP_GATEWAY != ROUTE_UP_GATEWAY {
+                return None;
+            }
+            Some(Route {
+                interface: fields.get(9)?.to_string(),
+                gateway: IpAddr::V6(Ipv6Addr::from(gateway)),
+                metric: u32::from_str_radix(fields.get(5)?, 16).ok()?,
+            })
+        })
+        .collect()
+}
+
+fn dns_servers() -> Vec<String> {
+    match std::fs::read_to_string("/etc/resolv.conf") {
+        Ok(resolv) => resolv
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("nameserver"))
+            .map(|server| server.trim().to_string())
+            .filter(|server| !server.is_empty())
+            .collect(),
+        Err(e) => {
+            error!("Failed to read /etc/resolv.conf: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn interface_addresses(interface: &str) -> Vec<String> {
+    let addrs = match getifaddrs() {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            error!("Failed to read interface addresses: {}", e);
+            return Vec::new();
+        }
+    };
+    addrs
+        .filter(|addr| addr.interface_name == interface)
+        .filter_map(|addr| {
+            let address = addr.address?;
+            if let Some(v4) = address.as_sockaddr_in() {
+                Some(v4.ip().to_string())
+            } else {
+                address.as_sockaddr_in6().map(|v6| v6.ip().to_string())
+            }
+        })
+        .collect()
+}
+
+#[utoipa::path(
+    get,
+    path = "/network/interfaces",
+    responses(
+        (status = 200, description = "Addresses, MAC, MTU and link state of each interface", body = InterfacesResponse),
+    )
+)]
+pub async fn get_network_interfaces() -> Json<InterfacesResponse> {
+    // Every interface but loopback with its link state, MAC, MTU and addresses,
+    // so a headless Pi's current address can be looked up
+    trace!("Fetching network interfaces for http request");
+    // Reading sysfs and the interface list blocks
+    let interfaces = tokio::task::spawn_blocking(|| read_interfaces(Path::new(NET_SYSFS))).await.unwrap_or_else(|e| {
+        error!("Network interfaces task failed: {}", e);
+        Vec::new()
+    });
+    Json(InterfacesResponse { interfaces })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InterfacesResponse {
+    interfaces: Vec<Link>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Link {
+    interface: String,
+    // Whether the interface is enabled, and whether its link is up
+    up: bool,
+    state: Option<String>,
+    mac: Option<String>,
+    mtu: Option<u32>,
+    // Addresses with their prefix length, e.g. 192.168.1.20/24
+    ipv4: Vec<String>,
+    ipv6: Vec<String>,
+}
+
+fn read_interfaces(root: &Path) -> Vec<Link> {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to read {}: {}", root.display(), e);
+            return Vec::new();
+        }
+    };
+    let mut addresses = all_addresses();
+    let mut interfaces: Vec<Link> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().to_str()?.to_string();
+            let addresses = addresses.remove(&name).unwrap_or_default();
+            link_info(&entry.path(), &name, addresses)
+        })
+        .collect();
+    interfaces.sort_by(|a, b| a.interface.cmp(&b.interface));
+    interfaces
+}
+
+// Link details of one interface directory, None for loopback
+fn link_info(dir: &Path, name: &str, addresses: Vec<IpCidr>) -> Option<Link> {
+    let read = |file: &str| std::fs::read_to_string(dir.join(file)).ok().map(|s| s.trim().to_string());
+    let flags = read("flags")
+        .and_then(|flags| u32::from_str_radix(flags.trim_start_matches("0x"), 16).ok())
+        .unwrap_or(0);
+    if flags & IFF_LOOPBACK != 0 {
+        return None;
+    }
+    let (ipv4, ipv6): (Vec<IpCidr>, Vec<IpCidr>) = addresses.into_iter().partition(|(ip, _)| ip.is_ipv4());
+    let cidr = |list: Vec<IpCidr>| list.iter().map(|(ip, prefix)| format!("{}/{}", ip, prefix)).collect::<Vec<String>>();
+    Some(Link {
+        interface: name.to_string(),
+        up: flags & IFF_UP != 0,
+        state: read("operstate"),
+        mac: read("address").filter(|mac| !mac.is_empty()),
+        mtu: read("mtu").and_then(|mtu| mtu.parse::<u32>().ok()),
+        ipv4: cidr(ipv4),
+        ipv6: cidr(ipv6),
+    })
+}
+
+// An address with its prefix length
+type IpCidr = (IpAddr, u32);
+
+fn all_addresses() -> HashMap<String, Vec<IpCidr>> {
+    let mut by_interface: HashMap<String, Vec<IpCidr>> = HashMap::new();
+    let addrs = match getifaddrs() {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            error!("Failed to read interface addresses: {}", e);
+            return by_interface;
+        }
+    };
+    for addr in addrs {
+        let (Some(address), Some(netmask)) = (addr.address, addr.netmask) else {
+            continue;
+        };
+        let entry = if let (Some(ip), Some(mask)) = (address.as_sockaddr_in(), netmask.as_sockaddr_in()) {
+            (IpAddr::V4(ip.ip()), mask.ip().to_bits().count_ones())
+        } else if let (Some(ip), Some(mask)) = (address.as_sockaddr_in6(), netmask.as_sockaddr_in6()) {
+            (IpAddr::V6(ip.ip()), mask.ip().to_bits().count_ones())
+        } else {
+            continue;
+        };
+        by_interface.entry(addr.interface_name).or_default().push(entry);
+    }
+    by_interface
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_link_info() {
+        let dir = crate::testing::temp_dir();
+        let root = dir.path();
+        for (name, flags) in [("wlan0", "0x1003"), ("lo", "0x9")] {
+            std::fs::create_dir_all(root.join(name)).unwrap();
+            std::fs::write(root.join(name).join("flags"), format!("{}\n", flags)).unwrap();
+        }
+        std::fs::write(root.join("wlan0/operstate"), "dormant\n").unwrap();
+        std::fs::write(root.join("wlan0/mtu"), "1500\n").unwrap();
+        std::fs::write(root.join("wlan0/address"), "dc:a6:32:01:02:03\n").unwrap();
+        let addresses = vec![("192.168.1.20".parse().unwrap(), 24), ("fe80::1".parse().unwrap(), 64)];
+        let wlan0 = link_info(&root.join("wlan0"), "wlan0", addresses).unwrap();
+        assert!(wlan0.up);
+        assert_eq!(wlan0.state.as_deref(), Some("dormant"));
+        assert_eq!(wlan0.mtu, Some(1500));
+        assert_eq!(wlan0.ipv4, ["192.168.1.20/24"]);
+        assert_eq!(wlan0.ipv6, ["fe80::1/64"]);
+        assert!(link_info(&root.join("lo"), "lo", Vec::new()).is_none());
+    }
+}
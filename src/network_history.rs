@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use log::{info, trace};
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::HistoryRow;
+
+// values_id is the values row the counters were logged with
+pub const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS network_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    interface TEXT NOT NULL,
+    rx_bytes INTEGER,
+    tx_bytes INTEGER,
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+    values_id INTEGER REFERENCES 'values'(id)
+    )";
+
+// Links counters logged before values_id existed to the first values row
+// with their timestamp, which is how they used to be joined
+pub fn add_values_id(conn: &Connection) -> rusqlite::Result<()> {
+    let has_column = conn
+        .prepare("SELECT 1 FROM pragma_table_info('network_history') WHERE name = 'values_id'")?
+        .exists([])?;
+    if !has_column {
+        info!("Linking network history to history rows");
+        conn.execute("ALTER TABLE network_history ADD COLUMN values_id INTEGER REFERENCES 'values'(id)", ())?;
+        conn.execute(
+            "UPDATE network_history SET values_id =
+            (SELECT v.id FROM 'values' v WHERE v.timestamp = network_history.timestamp ORDER BY v.id LIMIT 1)",
+            (),
+        )?;
+    }
+    Ok(())
+}
+
+// Counters of an interface logged with a history row
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InterfaceCounters {
+    interface: String,
+    rx_bytes: Option<i64>,
+    tx_bytes: Option<i64>,
+}
+
+// The network collector's value as the counters to log with a history row
+pub fn counters(network: &Value) -> Vec<InterfaceCounters> {
+    network
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|interface| InterfaceCounters {
+            interface: interface["interface"].as_str().unwrap_or_default().to_string(),
+            rx_bytes: interface["rx_bytes"].as_i64(),
+            tx_bytes: interface["tx_bytes"].as_i64(),
+        })
+        .collect()
+}
+
+pub fn insert(conn: &Connection, values_id: i64, timestamp: &str, counters: &[InterfaceCounters]) -> rusqlite::Result<()> {
+    // One row per interface, linked to the values row just written
+    for counters in counters {
+        trace!("Logging network counters for {}: Rx: {:?}, Tx: {:?}", counters.interface, counters.rx_bytes, counters.tx_bytes);
+        conn.execute(
+            "INSERT INTO network_history (values_id, interface, rx_bytes, tx_bytes, timestamp) VALUES (?, ?, ?, ?, ?)",
+            params![values_id, counters.interface, counters.rx_bytes, counters.tx_bytes, timestamp],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn attach(conn: &Connection, rows: &mut [HistoryRow]) -> Result<(), String> {
+    // Add a `network` array to each history row, read in one query over the
+    // id span of the rows
+    let ids = rows.iter().filter_map(|row| row.id);
+    let (Some(first), Some(last)) = (ids.clone().min(), ids.max()) else {
+        return Ok(());
+    };
+    let mut stmt = conn
+        .prepare(
+            "SELECT interface, rx_bytes, tx_bytes, values_id FROM network_history
+            WHERE values_id BETWEEN ? AND ? ORDER BY interface",
+        )
+        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let counters = stmt
+        .query_map(params![first, last], |row| {
+            Ok((
+                row.get::<_, i64>(3)?,
+                InterfaceCounters {
+                    interface: row.get(0)?,
+                    rx_bytes: row.get(1)?,
+                    tx_bytes: row.get(2)?,
+                },
+            ))
+        })
+        .map_err(|e| format!("Query execution failed: {}", e))?;
+    let mut by_row: HashMap<i64, Vec<InterfaceCounters>> = HashMap::new();
+    for row in counters {
+        let (values_id, interface) = row.map_err(|e| format!("Error processing row: {}", e))?;
+        by_row.entry(values_id).or_default().push(interface);
+    }
+    for row in rows.iter_mut() {
+        row.network = Some(row.id.and_then(|id| by_row.remove(&id)).unwrap_or_default());
+    }
+    Ok(())
+}
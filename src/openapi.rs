@@ -0,0 +1,157 @@
+use axum::Json;
+use axum::extract::State;
+use log::trace;
+use utoipa::openapi::path::{HttpMethod, OperationBuilder, ParameterBuilder, ParameterIn};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityRequirement, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, Object, OpenApi as Spec, Ref, ResponseBuilder, Type};
+use utoipa::OpenApi;
+
+use crate::AppState;
+use crate::collector::Registry;
+
+// Routes answered without PIDASH_API_TOKEN even when it is set
+const OPEN_PATHS: [&str; 2] = ["/health", "/ready"];
+// Routes checked with auth::check_token, which takes only the bearer token
+const BEARER_PATHS: [&str; 4] = ["/history/import", "/history/export-file", "/system/reboot", "/system/shutdown"];
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_openapi,
+        crate::metrics::get_metrics,
+        crate::metrics::get_influx,
+        crate::temp_peak::get_temp_peak,
+        crate::get_history,
+        crate::history_csv::get_history_csv,
+        crate::get_history_latest,
+        crate::disk_history::get_disk_history,
+        crate::anomaly::get_anomalies,
+        crate::history_events::get_history_events,
+        crate::history_summary::get_history_summary,
+        crate::import::import_history,
+        crate::export::export_history_file,
+        crate::control::post_reboot,
+        crate::control::post_shutdown,
+        crate::processes::get_processes,
+        crate::disk_wear::get_disk_writes,
+        crate::cluster::get_cluster,
+        crate::cluster::get_cluster_node,
+        crate::system_info::get_system_info,
+        crate::system_info::get_version,
+        crate::vm::get_vm,
+        crate::memory::get_mem_detailed,
+        crate::network::get_network_config,
+        crate::network::get_network_interfaces,
+        crate::wifi::get_wifi,
+        crate::diag::get_diag,
+        crate::capabilities::get_capabilities,
+        crate::thermal::get_throttled,
+        crate::thermal::get_temps,
+        crate::thermal::get_cooling,
+        crate::gpu::get_gpu,
+        crate::voltages::get_voltages,
+        crate::stream::get_stream,
+        crate::ws::get_ws,
+        crate::all::get_all,
+        crate::health::get_health,
+        crate::health::get_ready,
+    ),
+    components(schemas(crate::error::ErrorBody)),
+    info(description = "System metrics and history of a Raspberry Pi")
+)]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    responses(
+        (status = 200, description = "This OpenAPI document", body = serde_json::Value),
+    )
+)]
+pub async fn get_openapi(State(state): State<AppState>) -> Json<Spec> {
+    trace!("Building OpenAPI document for http request");
+    Json(spec(&state.collectors, state.config.api_token.is_some()))
+}
+
+pub fn spec(collectors: &Registry, token_required: bool) -> Spec {
+    // The annotated handlers, plus a path per registered collector, which are
+    // only known at runtime
+    let mut spec = ApiDoc::openapi();
+    // Cargo.toml names no license
+    spec.info.license = None;
+    for collector in collectors.iter() {
+        let mut operation = OperationBuilder::new()
+            .tag("collector")
+            .summary(Some(collector.description()))
+            .operation_id(Some(format!("get_{}", collector.name())))
+            .response("200", ResponseBuilder::new().description(collector.description()).content("application/json", ContentBuilder::new().schema(Some(collector.schema())).build()))
+            .response("400", error_response("Invalid query parameter"))
+            .response("500", error_response("Reading failed"));
+        for (name, description) in collector.params() {
+            operation = operation.parameter(
+                ParameterBuilder::new()
+                    .name(*name)
+                    .parameter_in(ParameterIn::Query)
+                    .description(Some(*description))
+                    .schema(Some(Object::with_type(Type::String))),
+            );
+        }
+        spec.paths.add_path_operation(format!("/{}", collector.name()), vec![HttpMethod::Get], operation);
+    }
+    if token_required {
+        // PIDASH_API_TOKEN as a bearer token, or ?token= for clients that
+        // can't set headers
+        let components = spec.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("bearer", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+        components.add_security_scheme("query_token", SecurityScheme::ApiKey(ApiKey::Query(ApiKeyValue::new("token"))));
+        let bearer = SecurityRequirement::new("bearer", Vec::<String>::new());
+        let query_token = SecurityRequirement::new("query_token", Vec::<String>::new());
+        for (path, item) in spec.paths.paths.iter_mut() {
+            let requirements = match path.as_str() {
+                path if OPEN_PATHS.contains(&path) => continue,
+                path if BEARER_PATHS.contains(&path) => vec![bearer.clone()],
+                _ => vec![bearer.clone(), query_token.clone()],
+            };
+            for operation in [&mut item.get, &mut item.post].into_iter().flatten() {
+                operation.security = Some(requirements.clone());
+            }
+        }
+    }
+    spec
+}
+
+fn error_response(description: &str) -> utoipa::openapi::Response {
+    ResponseBuilder::new()
+        .description(description)
+        .content("application/json", ContentBuilder::new().schema(Some(Ref::from_schema_name("ErrorBody"))).build())
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn describes_handlers_and_collectors() {
+        let registry = Registry::new(&Config::from_env(), Arc::new(Mutex::new(Default::default())));
+        let spec = serde_json::to_value(spec(&registry, true)).unwrap();
+        let paths = &spec["paths"];
+        let history_params: Vec<&str> = paths["/history"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| param["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(history_params, ["from", "to", "limit", "bucket", "include_network", "tz"]);
+        assert_eq!(paths["/cpu_usage"]["get"]["parameters"][0]["name"], "detailed");
+        let mem_usage = &paths["/mem_usage"]["get"]["responses"]["200"]["content"]["application/json"]["schema"];
+        assert_eq!(mem_usage["required"], serde_json::json!(["mem_used", "mem_total", "mem_percent"]));
+        assert_eq!(paths["/history/import"]["post"]["security"], serde_json::json!([{"bearer": []}]));
+        assert_eq!(paths["/history"]["get"]["security"], serde_json::json!([{"bearer": []}, {"query_token": []}]));
+        assert!(paths["/health"]["get"].get("security").is_none());
+        let collectors = registry.iter().filter(|collector| paths.get(format!("/{}", collector.name())).is_some());
+        assert_eq!(collectors.count(), registry.iter().count());
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use axum::Json;
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Response};
+use futures_util::stream::{self, StreamExt};
+use http::header;
+use log::trace;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::error::{ApiError, ApiResult};
+
+// Hard cap on the number of processes a single request can return
+const MAX_PROCESSES: usize = 500;
+// USER_HZ, the unit of the time fields in /proc/[pid]/stat
+const CLOCK_TICKS: f64 = 100.0;
+// Processes read at the same time while streaming the list
+const PROCESS_READ_CONCURRENCY: usize = 8;
+// How long a full walk of /proc is reused by ?top= requests
+const SNAPSHOT_TTL: Duration = Duration::from_secs(2);
+
+// Every process with all fields, as last read for a ?top= request
+#[derive(Default)]
+pub struct ProcessCache {
+    taken: Option<Instant>,
+    processes: Vec<Process>,
+}
+
+// A process with the fields asked for, the others are left out
+#[derive(Clone, Default, Serialize, ToSchema)]
+pub struct Process {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<String>,
+    // Percent of one core averaged over the process's lifetime
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpu: Option<f64>,
+    // Resident memory in kilobytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rss: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    threads: Option<u64>,
+}
+
+// The list streamed by /processes
+#[derive(Serialize, ToSchema)]
+pub struct ProcessList {
+    total: usize,
+    processes: Vec<Process>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TopProcesses {
+    total_processes: usize,
+    total_threads: u64,
+    // cpu or mem
+    by: String,
+    processes: Vec<Process>,
+}
+
+#[derive(Clone, Copy)]
+struct Fields {
+    pid: bool,
+    command: bool,
+    state: bool,
+    cpu: bool,
+    rss: bool,
+    threads: bool,
+}
+
+impl Fields {
+    const ALL: Fields = Fields {
+        pid: true,
+        command: true,
+        state: true,
+        cpu: true,
+        rss: true,
+        threads: true,
+    };
+
+    fn parse(list: &str) -> Result<Fields, String> {
+        let mut fields = Fields {
+            pid: false,
+            command: false,
+            state: false,
+            cpu: false,
+            rss: false,
+            threads: false,
+        };
+        for name in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match name {
+                "pid" => fields.pid = true,
+                "command" => fields.command = true,
+                "state" => fields.state = true,
+                "cpu" => fields.cpu = true,
+                "rss" => fields.rss = true,
+                "threads" => fields.threads = true,
+                _ => return Err(format!("Unknown process field: {}", name)),
+            }
+        }
+        Ok(fields)
+    }
+
+    fn needs_stat(&self) -> bool {
+        self.state || self.cpu
+    }
+
+    fn needs_status(&self) -> bool {
+        self.rss || self.threads
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/processes",
+    params(
+        ("fields" = Option<String>, Query, description = "Comma separated fields to include, all by default"),
+        ("limit" = Option<usize>, Query, description = "Maximum processes listed"),
+        ("top" = Option<usize>, Query, description = "Return the N processes using the most CPU or memory instead"),
+        ("by" = Option<String>, Query, description = "cpu (default) or mem, with top"),
+    ),
+    responses(
+        (status = 200, description = "Running processes, or with top the busiest ones", body = ProcessList),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 500, description = "Reading failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_processes(State(state): State<AppState>, Query(params): Query<HashMap<String, String>>) -> Response {
+    // Stream the process list so large process tables are never built up in memory
+    trace!("Fetching process list with parameters: {:?}", params);
+    if let Some(top) = params.get("top") {
+        return get_top(&state, top, params.get("by")).await.into_response();
+    }
+    let fields = match params.get("fields") {
+        Some(list) => match Fields::parse(list) {
+            Ok(fields) => fields,
+            Err(e) => return ApiError::bad_request(e).into_response(),
+        },
+        None => Fields::ALL,
+    };
+    let limit = match params.get("limit") {
+        Some(limit) => match limit.parse::<usize>() {
+            Ok(limit) => limit.min(MAX_PROCESSES),
+            Err(_) => return ApiError::bad_request(format!("Invalid limit {:?}, expected a number", limit)).into_response(),
+        },
+        None => MAX_PROCESSES,
+    };
+    let listed = tokio::task::spawn_blocking(move || {
+        let uptime = if fields.cpu { read_uptime_secs() } else { 0.0 };
+        list_pids().map(|pids| (pids, uptime))
+    })
+    .await;
+    let (pids, uptime) = match listed {
+        Ok(Ok(listed)) => listed,
+        Ok(Err(e)) => return ApiError::internal(format!("Failed to read process list: {}", e)).into_response(),
+        Err(e) => return ApiError::internal(format!("Process list task failed: {}", e)).into_response(),
+    };
+    trace!("Streaming up to {} of {} processes", limit, pids.len());
+    let total = pids.len();
+
+    // Each process is read off the runtime, a few at a time and in pid order
+    let entries = stream::iter(pids)
+        .map(move |pid| tokio::task::spawn_blocking(move || read_process(pid, fields, uptime)))
+        .buffered(PROCESS_READ_CONCURRENCY)
+        .filter_map(|process| async move { process.ok().flatten() })
+        .take(limit)
+        .enumerate()
+        .map(|(i, process)| {
+            let separator = if i == 0 { "" } else { "," };
+            Ok::<_, Infallible>(format!("{}{}", separator, serde_json::to_string(&process).unwrap_or_default()))
+        });
+    let body = stream::once(async move { Ok::<_, Infallible>(format!("{{\"total\":{},\"processes\":[", total)) })
+        .chain(entries)
+        .chain(stream::once(async { Ok("]}".to_string()) }));
+
+    ([(header::CONTENT_TYPE, "application/json")], Body::from_stream(body)).into_response()
+}
+
+async fn get_top(state: &AppState, top: &str, by: Option<&String>) -> ApiResult<TopProcesses> {
+    // The N processes using the most CPU or memory, with process and thread
+    // totals. Sorting needs every process, so the walk is cached briefly.
+    let top = top
+        .parse::<usize>()
+        .map_err(|_| ApiError::bad_request(format!("Invalid top {:?}, expected a number", top)))?
+        .min(MAX_PROCESSES);
+    let key: fn(&Process) -> f64 = match by.map(String::as_str) {
+        None | Some("cpu") => |process| process.cpu.unwrap_or(0.0),
+        Some("mem") => |process| process.rss.unwrap_or(0) as f64,
+        Some(by) => return Err(ApiError::bad_request(format!("Invalid by {:?}, expected cpu or mem", by))),
+    };
+    let fresh = {
+        let cache = state.process_cache.lock().unwrap();
+        cache
+            .taken
+            .filter(|taken| taken.elapsed() < SNAPSHOT_TTL)
+            .map(|_| cache.processes.clone())
+    };
+    let mut processes = match fresh {
+        Some(processes) => processes,
+        None => {
+            let processes = tokio::task::spawn_blocking(read_all)
+                .await
+                .map_err(|e| ApiError::internal(format!("Process list task failed: {}", e)))?
+                .map_err(|e| ApiError::internal(format!("Failed to read process list: {}", e)))?;
+            let mut cache = state.process_cache.lock().unwrap();
+            cache.taken = Some(Instant::now());
+            cache.processes = processes.clone();
+            processes
+        }
+    };
+    let total_threads: u64 = processes.iter().filter_map(|process| process.threads).sum();
+    let total_processes = processes.len();
+    rank(&mut processes, key, top);
+    Ok(Json(TopProcesses {
+        total_processes,
+        total_threads,
+        by: by.map(String::as_str).unwrap_or("cpu").to_string(),
+        processes,
+    }))
+}
+
+// Keep the top processes by key, highest first
+fn rank(processes: &mut Vec<Process>, key: fn(&Process) -> f64, top: usize) {
+    processes.sort_by(|a, b| key(b).total_cmp(&key(a)));
+    processes.truncate(top);
+}
+
+fn read_all() -> std::io::Result<Vec<Process>> {
+    let uptime = read_uptime_secs();
+    Ok(list_pids()?
+        .into_iter()
+        .filter_map(|pid| read_process(pid, Fields::ALL, uptime))
+        .collect())
+}
+
+fn list_pids() -> std::io::Result<Vec<u32>> {
+    let mut pids: Vec<u32> = std::fs::read_dir("/proc")?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .collect();
+    pids.sort_unstable();
+    Ok(pids)
+}
+
+fn read_uptime_secs() -> f64 {
+    std::fs::read_to_string("/proc/uptime")
+        .ok()
+        .and_then(|s| s.split_whitespace().next()?.parse::<f64>().ok())
+        .unwrap_or(0.0)
+}
+
+fn read_process(pid: u32, fields: Fields, uptime: f64) -> Option<Process> {
+    // Only touch the /proc files needed for the requested fields. A process
+    // that exits while being read is skipped.
+    let mut process = Process::default();
+    if fields.pid {
+        process.pid = Some(pid);
+    }
+    if fields.command {
+        process.command = Some(read_command(pid)?);
+    }
+    if fields.needs_stat() {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        let (state, cpu) = parse_stat(&stat, uptime)?;
+        if fields.state {
+            process.state = Some(state);
+        }
+        if fields.cpu {
+            process.cpu = Some(cpu);
+        }
+    }
+    if fields.needs_status() {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        if fields.rss {
+            // Kernel threads have no VmRSS line
+            let rss = status_value(&status, "VmRSS:").unwrap_or(0);
+            process.rss = Some(rss);
+        }
+        if fields.threads {
+            process.threads = Some(status_value(&status, "Threads:")?);
+        }
+    }
+    Some(process)
+}
+
+// State and lifetime CPU percent from a /proc/[pid]/stat line
+fn parse_stat(stat: &str, uptime: f64) -> Option<(String, f64)> {
+    // The command name is wrapped in parentheses and may itself contain spaces
+    // or parentheses, the fields after it start with the state
+    let rest: Vec<&str> = stat.get(stat.rfind(')')? + 1..)?.split_whitespace().collect();
+    let state = rest.first()?.to_string();
+    // utime, stime and starttime, fields 14, 15 and 22 of the whole line
+    let utime = rest.get(11)?.parse::<f64>().ok()?;
+    let stime = rest.get(12)?.parse::<f64>().ok()?;
+    let start_time = rest.get(19)?.parse::<f64>().ok()? / CLOCK_TICKS;
+    let elapsed = uptime - start_time;
+    let cpu = if elapsed > 0.0 {
+        (utime + stime) / CLOCK_TICKS / elapsed * 100.0
+    } else {
+        0.0
+    };
+    Some((state, cpu))
+}
+
+fn read_command(pid: u32) -> Option<String> {
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    if let Some(command) = join_cmdline(&cmdline) {
+        return Some(command);
+    }
+    // Kernel threads have an empty cmdline, show their name in brackets like ps does
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(format!("[{}]", comm.trim()))
+}
+
+// The NUL separated arguments joined with spaces, None when there are none
+fn join_cmdline(cmdline: &[u8]) -> Option<String> {
+    let command = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!command.is_empty()).then_some(command)
+}
+
+fn status_value(status: &str, key: &str) -> Option<u64> {
+    status
+        .lines()
+        .find(|line| line.starts_with(key))?
+        .split_whitespace()
+        .nth(1)?
+        .parse::<u64>()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stat_after_the_command_name() {
+        // A name with spaces and a closing parenthesis, started 100s after boot
+        // with 30s of user and 10s of system time
+        let stat = "1234 (my (weird) app) S 1 1234 1234 0 -1 4194560 100 0 0 0 3000 1000 0 0 20 0 4 0 10000 1000000 200 18446744073709551615\n";
+        let (state, cpu) = parse_stat(stat, 300.0).unwrap();
+        assert_eq!(state, "S");
+        assert_eq!(cpu, 20.0);
+        // Started this very tick
+        assert_eq!(parse_stat(stat, 100.0).unwrap().1, 0.0);
+        assert!(parse_stat("1234 (truncated) S 1", 300.0).is_none());
+        assert!(parse_stat("no parenthesis", 300.0).is_none());
+    }
+
+    #[test]
+    fn reads_status_values() {
+        let status = "Name:\tbash\nState:\tS (sleeping)\nVmRSS:\t    3520 kB\nThreads:\t1\n";
+        assert_eq!(status_value(status, "VmRSS:"), Some(3520));
+        assert_eq!(status_value(status, "Threads:"), Some(1));
+        assert_eq!(status_value(status, "VmSwap:"), None);
+        assert_eq!(status_value(status, "Name:"), None);
+    }
+
+    #[test]
+    fn joins_command_lines() {
+        assert_eq!(join_cmdline(b"/usr/bin/python3\0-m\0http.server\0").as_deref(), Some("/usr/bin/python3 -m http.server"));
+        // Kernel threads have none and fall back to [comm]
+        assert_eq!(join_cmdline(b""), None);
+        assert_eq!(join_cmdline(b"\0\0"), None);
+    }
+
+    #[test]
+    fn parses_field_lists() {
+        let fields = Fields::parse("pid, rss,").unwrap();
+        assert!(fields.pid && fields.rss);
+        assert!(!fields.command && !fields.cpu);
+        assert!(fields.needs_status() && !fields.needs_stat());
+        assert!(Fields::parse("pid,memory").is_err());
+    }
+
+    #[test]
+    fn ranks_the_busiest_processes() {
+        let process = |pid, cpu, rss| Process {
+            pid: Some(pid),
+            cpu: Some(cpu),
+            rss: Some(rss),
+            ..Default::default()
+        };
+        let mut processes = vec![process(1, 0.5, 900), process(2, 12.0, 100), process(3, 3.0, 5000)];
+        rank(&mut processes, |process| process.cpu.unwrap_or(0.0), 2);
+        assert_eq!(processes.iter().map(|p| p.pid).collect::<Vec<_>>(), [Some(2), Some(3)]);
+        let mut processes = vec![process(1, 0.5, 900), process(2, 12.0, 100), process(3, 3.0, 5000)];
+        rank(&mut processes, |process| process.rss.unwrap_or(0) as f64, 5);
+        assert_eq!(processes.iter().map(|p| p.pid).collect::<Vec<_>>(), [Some(3), Some(1), Some(2)]);
+    }
+}
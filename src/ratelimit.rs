@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::extract::{FromRequestParts, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum_client_ip::ClientIp;
+use http::header::RETRY_AFTER;
+use log::warn;
+
+use crate::AppState;
+use crate::config::Config;
+use crate::error::ApiError;
+
+// Number of tracked clients above which idle buckets are dropped
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+// Token bucket per client IP, refilled at `rate` tokens a second up to `burst`
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> RateLimiter {
+        RateLimiter {
+            rate: config.rate_limit_per_sec,
+            burst: config.rate_limit_burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Take a token for `ip`, false when its bucket is empty. Loopback clients
+    // and a rate of 0 are never limited.
+    pub fn allow(&self, ip: IpAddr, now: Instant) -> bool {
+        if self.rate <= 0.0 || ip.to_canonical().is_loopback() {
+            return true;
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            // A bucket that has refilled completely holds no state worth keeping
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.refilled_at).as_secs_f64() * rate < burst);
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled_at = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+pub async fn limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    // Requests whose client IP is unknown are let through
+    let (mut parts, body) = request.into_parts();
+    if let Ok(ClientIp(ip)) = ClientIp::from_request_parts(&mut parts, &()).await
+        && !state.rate_limiter.allow(ip, Instant::now())
+    {
+        warn!("Rate limit exceeded by {}", ip);
+        let error = ApiError::too_many_requests(format!(
+            "Rate limit of {} requests per second exceeded",
+            state.config.rate_limit_per_sec
+        ));
+        return ([(RETRY_AFTER, "1")], error).into_response();
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limiter(rate: f64, burst: u32) -> RateLimiter {
+        let mut config = Config::from_env();
+        config.rate_limit_per_sec = rate;
+        config.rate_limit_burst = burst;
+        RateLimiter::new(&config)
+    }
+
+    #[test]
+    fn limits_each_client_to_its_bucket() {
+        let limiter = limiter(2.0, 3);
+        let client: IpAddr = "192.168.1.20".parse().unwrap();
+        let other: IpAddr = "192.168.1.21".parse().unwrap();
+        let start = Instant::now();
+        assert!((0..3).all(|_| limiter.allow(client, start)));
+        assert!(!limiter.allow(client, start));
+        // Another client has its own bucket
+        assert!(limiter.allow(other, start));
+        // Half a second refills one token at 2 per second
+        assert!(limiter.allow(client, start + Duration::from_millis(500)));
+        assert!(!limiter.allow(client, start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn exempts_localhost_and_disabled_limit() {
+        let strict = limiter(1.0, 1);
+        let now = Instant::now();
+        for ip in ["127.0.0.1", "::1", "::ffff:127.0.0.1"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert!((0..10).all(|_| strict.allow(ip, now)));
+        }
+        let disabled = limiter(0.0, 1);
+        let client: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!((0..10).all(|_| disabled.allow(client, now)));
+    }
+}
@@ -0,0 +1,54 @@
+use log::{info, trace};
+use rusqlite::{Connection, params};
+
+// History tables pruned by age, all keyed by a UTC `timestamp` column
+const HISTORY_TABLES: [&str; 3] = ["'values'", "disk_history", "network_history"];
+
+pub fn prune(conn: &Connection, days: u64) -> Result<usize, String> {
+    // Delete history older than `days`, 0 keeps everything
+    if days == 0 {
+        return Ok(0);
+    }
+    let cutoff = format!("-{} days", days);
+    let mut deleted = 0;
+    for table in HISTORY_TABLES {
+        deleted += conn
+            .execute(
+                &format!("DELETE FROM {} WHERE timestamp < datetime('now', ?)", table),
+                params![cutoff],
+            )
+            .map_err(|e| format!("Failed to prune {}: {}", table, e))?;
+    }
+    if deleted > 0 {
+        info!("Pruned {} history rows older than {} days", deleted, days);
+    } else {
+        trace!("No history older than {} days to prune", days);
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Db, create_schema};
+
+    #[test]
+    fn prunes_only_rows_past_retention() {
+        let db = Db::new(":memory:").unwrap();
+        let conn = db.open().unwrap();
+        create_schema(&conn);
+        for timestamp in ["2000-01-01 00:00:00", "2999-01-01 00:00:00"] {
+            conn.execute(
+                "INSERT INTO 'values' (cpu_usage, mem_total, mem_used, timestamp) VALUES (1.0, 1, 1, ?)",
+                [timestamp],
+            )
+            .unwrap();
+            conn.execute("INSERT INTO network_history (interface, timestamp) VALUES ('eth0', ?)", [timestamp])
+                .unwrap();
+        }
+        assert_eq!(prune(&conn, 0).unwrap(), 0);
+        assert_eq!(prune(&conn, 30).unwrap(), 2);
+        let remaining: i64 = conn.query_row("SELECT COUNT(*) FROM 'values'", [], |row| row.get(0)).unwrap();
+        assert_eq!(remaining, 1);
+    }
+}
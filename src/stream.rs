@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::extract::{Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::Stream;
+use futures_util::stream;
+use log::{error, trace};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::time::{MissedTickBehavior, interval};
+
+use crate::AppState;
+
+pub const DEFAULT_INTERVAL_SECS: u64 = 2;
+// Bounds on ?interval=, collecting everything takes a few hundred milliseconds
+pub const MIN_INTERVAL_SECS: u64 = 1;
+pub const MAX_INTERVAL_SECS: u64 = 300;
+
+// One /stream event or /ws message
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Frame {
+    Metrics {
+        timestamp: String,
+        metrics: HashMap<&'static str, Value>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+impl Frame {
+    pub fn metrics(metrics: HashMap<&'static str, Value>) -> Self {
+        Frame::Metrics {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            metrics,
+        }
+    }
+
+    pub fn error(error: impl Into<String>) -> Self {
+        Frame::Error { error: error.into() }
+    }
+
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/stream",
+    params(
+        ("interval" = Option<u64>, Query, description = "Seconds between events, 1 to 300, 2 by default"),
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events with every collector's value", content_type = "text/event-stream", body = String),
+    )
+)]
+pub async fn get_stream(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // Push every collector's value as one JSON event per interval. The stream is
+    // polled by the connection itself, so it is dropped along with the client
+    // and no task outlives it.
+    let secs = params
+        .get("interval")
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECS)
+        .clamp(MIN_INTERVAL_SECS, MAX_INTERVAL_SECS);
+    trace!("Starting metrics stream every {}s", secs);
+    let mut ticker = interval(Duration::from_secs(secs));
+    // A slow collection pushes the next event back instead of bursting
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let events = stream::unfold((state, ticker), |(state, mut ticker)| async move {
+        ticker.tick().await;
+        let task_state = state.clone();
+        // Collectors may block, cpu_usage sleeps between its two samples
+        let data = match tokio::task::spawn_blocking(move || task_state.collectors.collect_all()).await {
+            Ok(values) => Frame::metrics(values),
+            Err(e) => {
+                error!("Collector task failed: {}", e);
+                Frame::error("Collector task failed")
+            }
+        };
+        let event = Event::default().data(data.to_text());
+        Some((Ok(event), (state, ticker)))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,157 @@
+use axum::Json;
+use axum::extract::State;
+use log::{info, trace, warn};
+use nix::sys::utsname::{UtsName, uname};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+// Static board identification, read once at startup
+pub struct SystemInfo {
+    pub model: Option<String>,
+    pub revision: Option<String>,
+    pub serial: Option<String>,
+    pub unique_id: Option<String>,
+    // Kernel release and version as reported by uname, e.g. "6.6.31+rpt-rpi-v8"
+    pub kernel_release: Option<String>,
+    pub kernel_version: Option<String>,
+    pub machine: Option<String>,
+    // PRETTY_NAME of /etc/os-release, e.g. "Debian GNU/Linux 12 (bookworm)"
+    pub os_release: Option<String>,
+}
+
+impl SystemInfo {
+    pub fn read() -> SystemInfo {
+        let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+        let uname = uname().inspect_err(|e| warn!("Failed to read uname: {}", e)).ok();
+        let uname_field = |field: fn(&UtsName) -> &std::ffi::OsStr| {
+            uname.as_ref().map(|uname| field(uname).to_string_lossy().into_owned())
+        };
+        let os_release = std::fs::read_to_string("/etc/os-release").unwrap_or_default();
+        let info = SystemInfo {
+            model: read_device_tree("model").or_else(|| cpuinfo_field(&cpuinfo, "Model")),
+            revision: cpuinfo_field(&cpuinfo, "Revision"),
+            serial: cpuinfo_field(&cpuinfo, "Serial"),
+            unique_id: read_device_tree("serial-number"),
+            kernel_release: uname_field(UtsName::release),
+            kernel_version: uname_field(UtsName::version),
+            machine: uname_field(UtsName::machine),
+            os_release: os_release_name(&os_release),
+        };
+        info!(
+            "Board model: {}, serial: {}",
+            info.model.as_deref().unwrap_or("unknown"),
+            info.serial.as_deref().unwrap_or("unknown")
+        );
+        info
+    }
+}
+
+fn cpuinfo_field(cpuinfo: &str, key: &str) -> Option<String> {
+    // Lines look like "Serial\t\t: 10000000abcdef01"; non-Pi hardware lacks most of them
+    cpuinfo
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn os_release_name(os_release: &str) -> Option<String> {
+    // PRETTY_NAME="Debian GNU/Linux 12 (bookworm)", NAME when there is no PRETTY_NAME
+    let field = |key: &str| {
+        os_release
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(|value| value.trim().trim_matches('"').to_string())
+            .filter(|value| !value.is_empty())
+    };
+    field("PRETTY_NAME").or_else(|| field("NAME"))
+}
+
+fn read_device_tree(node: &str) -> Option<String> {
+    // Device tree strings are NUL terminated
+    std::fs::read_to_string(format!("/proc/device-tree/{}", node))
+        .ok()
+        .map(|s| s.trim_end_matches('\0').trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+#[utoipa::path(
+    get,
+    path = "/system_info",
+    responses(
+        (status = 200, description = "Hostname, board model, revision and serial number", body = SystemInfoResponse),
+    )
+)]
+pub async fn get_system_info(State(state): State<AppState>) -> Json<SystemInfoResponse> {
+    trace!("Fetching system info for http request");
+    let info = &state.system_info;
+    let hostname = tokio::fs::read_to_string("/proc/sys/kernel/hostname")
+        .await
+        .ok()
+        .map(|h| h.trim().to_string());
+    Json(SystemInfoResponse {
+        hostname,
+        model: info.model.clone(),
+        revision: info.revision.clone(),
+        serial: info.serial.clone(),
+        unique_id: info.unique_id.clone(),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SystemInfoResponse {
+    hostname: Option<String>,
+    model: Option<String>,
+    revision: Option<String>,
+    serial: Option<String>,
+    unique_id: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/version",
+    responses(
+        (status = 200, description = "pidash version with its git commit, the kernel and the OS release", body = VersionResponse),
+    )
+)]
+pub async fn get_version(State(state): State<AppState>) -> Json<VersionResponse> {
+    // What is running where, for bug reports
+    trace!("Fetching version for http request");
+    let info = &state.system_info;
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: option_env!("PIDASH_GIT_COMMIT"),
+        model: info.model.clone(),
+        kernel_release: info.kernel_release.clone(),
+        kernel_version: info.kernel_version.clone(),
+        machine: info.machine.clone(),
+        os_release: info.os_release.clone(),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct VersionResponse {
+    version: &'static str,
+    git_commit: Option<&'static str>,
+    model: Option<String>,
+    kernel_release: Option<String>,
+    kernel_version: Option<String>,
+    machine: Option<String>,
+    os_release: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_os_release_name() {
+        let debian = "PRETTY_NAME=\"Debian GNU/Linux 12 (bookworm)\"\nNAME=\"Debian GNU/Linux\"\nVERSION_ID=\"12\"\n";
+        assert_eq!(os_release_name(debian).as_deref(), Some("Debian GNU/Linux 12 (bookworm)"));
+        assert_eq!(os_release_name("NAME=Alpine\n").as_deref(), Some("Alpine"));
+        assert_eq!(os_release_name(""), None);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use axum::Json;
+use axum::extract::{Query, State};
+use log::trace;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::error::{ApiError, ApiResult};
+use crate::thermal::TempUnit;
+
+// Millidegrees Celsius as logged
+#[derive(Clone)]
+struct Peak {
+    temp: i32,
+    timestamp: String,
+}
+
+// A peak as reported, in the unit asked for
+#[derive(Serialize, ToSchema)]
+pub struct PeakReading {
+    temp: f64,
+    timestamp: String,
+}
+
+// All-time and today's highest logged temperature. Loaded from history on
+// first use, then kept up to date by the logger so requests never scan the table.
+#[derive(Default)]
+pub struct PeakCache {
+    loaded: bool,
+    // Bumped by invalidate, so a load started before it isn't kept
+    generation: u64,
+    all_time: Option<Peak>,
+    today: Option<Peak>,
+}
+
+// The peaks in the history table, queried without holding the cache lock
+struct Loaded {
+    generation: u64,
+    all_time: Option<Peak>,
+    today: Option<Peak>,
+}
+
+impl Loaded {
+    fn query(conn: &Connection, generation: u64) -> Result<Loaded, String> {
+        let query = |filter: &str| {
+            conn.query_row(
+                &format!(
+                    "SELECT cpu_temp, timestamp FROM 'values' WHERE cpu_temp IS NOT NULL {} ORDER BY cpu_temp DESC LIMIT 1",
+                    filter
+                ),
+                [],
+                |row| {
+                    Ok(Peak {
+                        temp: row.get(0)?,
+                        timestamp: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(|e| format!("Failed to query peak temperature: {}", e))
+        };
+        Ok(Loaded {
+            generation,
+            all_time: query("")?,
+            // Timestamps are stored in UTC, so "today" is the current UTC day
+            today: query("AND timestamp >= date('now')")?,
+        })
+    }
+}
+
+impl PeakCache {
+    fn install(&mut self, loaded: Loaded) {
+        if self.loaded || loaded.generation != self.generation {
+            return;
+        }
+        // Samples the logger recorded while the query ran are folded back in
+        let recorded = [self.all_time.take(), self.today.take()];
+        self.all_time = loaded.all_time;
+        self.today = loaded.today;
+        self.loaded = true;
+        for peak in recorded.into_iter().flatten() {
+            self.record(peak.temp, &peak.timestamp);
+        }
+    }
+
+    // History changed other than by logging, e.g. an import, so the peaks are
+    // loaded again on the next request
+    pub fn invalidate(&mut self) {
+        *self = PeakCache {
+            generation: self.generation + 1,
+            ..Default::default()
+        };
+    }
+
+    // Fold a newly logged sample into the cached peaks
+    pub fn record(&mut self, temp: i32, timestamp: &str) {
+        let sample = Peak {
+            temp,
+            timestamp: timestamp.to_string(),
+        };
+        if self.all_time.as_ref().is_none_or(|peak| temp > peak.temp) {
+            self.all_time = Some(sample.clone());
+        }
+        // A peak from an earlier day no longer counts as today's
+        let day = &timestamp[..10.min(timestamp.len())];
+        if self.today.as_ref().is_none_or(|peak| !peak.timestamp.starts_with(day) || temp > peak.temp) {
+            self.today = Some(sample);
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/cpu_temp/peak",
+    params(
+        ("unit" = Option<String>, Query, description = "c or f, Celsius by default"),
+    ),
+    responses(
+        (status = 200, description = "Current CPU temperature with the highest logged today and over all time", body = PeakResponse),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 500, description = "Database query failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_temp_peak(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> ApiResult<PeakResponse> {
+    // Report the current temperature with the highest ones ever and today logged
+    trace!("Fetching peak temperature for http request");
+    let unit = TempUnit::from_params(&params).map_err(ApiError::bad_request)?;
+    // Loading the peaks queries the whole history once
+    let value = tokio::task::spawn_blocking(move || read_peaks(&state, unit))
+        .await
+        .map_err(|e| ApiError::internal(format!("Peak temperature task failed: {}", e)))?
+        .map_err(ApiError::internal)?;
+    Ok(Json(value))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PeakResponse {
+    current: Option<f64>,
+    today: Option<PeakReading>,
+    all_time: Option<PeakReading>,
+    unit: &'static str,
+}
+
+fn read_peaks(state: &AppState, unit: TempUnit) -> Result<PeakResponse, String> {
+    // The lock isn't held over the query, the logger records into the cache
+    // meanwhile. A load overtaken by an import is run again.
+    let cache = loop {
+        let cache = state.temp_peak.lock().unwrap();
+        if cache.loaded {
+            break cache;
+        }
+        let generation = cache.generation;
+        drop(cache);
+        let conn = state.db.open().map_err(|e| format!("Failed to open database: {}", e))?;
+        let loaded = Loaded::query(&conn, generation)?;
+        state.temp_peak.lock().unwrap().install(loaded);
+    };
+    // Today's peak goes stale at midnight if nothing has been logged since
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    let today_peak = cache.today.as_ref().filter(|peak| peak.timestamp.starts_with(&today));
+    let reading = |peak: &Peak| PeakReading {
+        temp: unit.convert_millis(peak.temp),
+        timestamp: peak.timestamp.clone(),
+    };
+    Ok(PeakResponse {
+        current: crate::read_cpu_temp(&state.config).map(|temp| unit.convert_millis(temp)),
+        today: today_peak.map(reading),
+        all_time: cache.all_time.as_ref().map(reading),
+        unit: unit.symbol(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn today_peak_resets_on_a_new_day() {
+        let mut cache = PeakCache::default();
+        cache.record(70000, "2024-01-01 12:00:00");
+        cache.record(65000, "2024-01-01 13:00:00");
+        cache.record(50000, "2024-01-02 00:01:00");
+        assert_eq!(cache.all_time.as_ref().unwrap().temp, 70000);
+        assert_eq!(cache.today.as_ref().unwrap().temp, 50000);
+    }
+
+    #[test]
+    fn load_keeps_samples_recorded_meanwhile() {
+        let peak = |temp, timestamp: &str| {
+            Some(Peak {
+                temp,
+                timestamp: timestamp.to_string(),
+            })
+        };
+        let mut cache = PeakCache::default();
+        cache.record(72000, "2024-01-02 10:00:00");
+        cache.install(Loaded {
+            generation: 0,
+            all_time: peak(80000, "2024-01-01 12:00:00"),
+            today: peak(60000, "2024-01-02 09:00:00"),
+        });
+        assert_eq!(cache.all_time.as_ref().unwrap().temp, 80000);
+        assert_eq!(cache.today.as_ref().unwrap().temp, 72000);
+        // A load started before an import is dropped
+        cache.invalidate();
+        cache.install(Loaded {
+            generation: 0,
+            all_time: peak(80000, "2024-01-01 12:00:00"),
+            today: None,
+        });
+        assert!(!cache.loaded && cache.all_time.is_none());
+    }
+}
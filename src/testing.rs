@@ -0,0 +1,7 @@
+// Fixtures shared by the tests of several modules
+
+// A fresh directory, removed with everything in it when dropped, also when
+// the test fails
+pub fn temp_dir() -> tempfile::TempDir {
+    tempfile::Builder::new().prefix("pidash-").tempdir().unwrap()
+}
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use axum::Json;
+use axum::extract::{Query, State};
+use log::{error, trace};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::command;
+use crate::config::Config;
+use crate::error::{ApiError, ApiResult};
+
+// Bits of the `vcgencmd get_throttled` mask, the same flags shifted left by 16
+// mean the condition has occurred at some point since boot
+const UNDER_VOLTAGE: u32 = 1 << 0;
+const FREQ_CAPPED: u32 = 1 << 1;
+const THROTTLED: u32 = 1 << 2;
+const SOFT_TEMP_LIMIT: u32 = 1 << 3;
+const OCCURRED_SHIFT: u32 = 16;
+// Thermal zones and cooling devices
+const THERMAL_SYSFS: &str = "/sys/class/thermal";
+// The same mask as exposed by the firmware driver, without running vcgencmd
+const FIRMWARE_THROTTLED: &str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+
+// Unit temperatures are reported in, picked with ?unit=c or ?unit=f
+#[derive(Clone, Copy)]
+pub enum TempUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+impl TempUnit {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<TempUnit, String> {
+        match params.get("unit").map(|unit| unit.to_ascii_lowercase()).as_deref() {
+            None | Some("c") | Some("celsius") => Ok(TempUnit::Celsius),
+            Some("f") | Some("fahrenheit") => Ok(TempUnit::Fahrenheit),
+            Some(_) => Err(format!("Invalid unit: {}, expected c or f", params["unit"])),
+        }
+    }
+
+    // Rounded to the millidegree precision of the sensors
+    pub fn convert(self, celsius: f64) -> f64 {
+        let temp = match self {
+            TempUnit::Celsius => celsius,
+            TempUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+        };
+        (temp * 1000.0).round() / 1000.0
+    }
+
+    // A sysfs or history reading, which are in millidegrees Celsius
+    pub fn convert_millis(self, millis: i32) -> f64 {
+        self.convert(millis as f64 / 1000.0)
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            TempUnit::Celsius => "C",
+            TempUnit::Fahrenheit => "F",
+        }
+    }
+}
+
+struct Trip {
+    index: usize,
+    kind: String,
+    temp: i32,
+}
+
+#[utoipa::path(
+    get,
+    path = "/throttled",
+    params(
+        ("unit" = Option<String>, Query, description = "c or f for the temperatures, Celsius by default"),
+    ),
+    responses(
+        (status = 200, description = "Throttling state and the thermal trip points", body = ThrottledResponse),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_throttled(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> ApiResult<ThrottledResponse> {
+    // Combine the firmware throttle flags with the thermal zone trip points so a
+    // throttle can be attributed to the trip the temperature had crossed
+    trace!("Fetching thermal health for http request");
+    let unit = TempUnit::from_params(&params).map_err(ApiError::bad_request)?;
+    let thermal_zone = state.config.thermal_zone.clone();
+    let (temp, trips) = tokio::task::spawn_blocking(move || {
+        // Trip points live next to the temperature file of the zone
+        let zone = Path::new(&thermal_zone).parent().unwrap_or(Path::new("/"));
+        (read_i32(&thermal_zone), read_trips(zone))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        error!("Thermal zone task failed: {}", e);
+        (None, Vec::new())
+    });
+    let active: Vec<&Trip> = match temp {
+        Some(temp) => trips.iter().filter(|trip| temp >= trip.temp).collect(),
+        None => Vec::new(),
+    };
+    let (throttle, source) = match read_throttled(&state.config).await {
+        Ok((mask, source)) => (Some(mask), Some(source)),
+        Err(e) => {
+            error!("{}", e);
+            (None, None)
+        }
+    };
+    // Throttling while a trip point is crossed is thermal, otherwise it comes
+    // from the firmware's own limits such as under-voltage
+    let thermal_trip = match throttle {
+        Some(mask) if mask & (FREQ_CAPPED | THROTTLED | SOFT_TEMP_LIMIT) != 0 => {
+            active.iter().max_by_key(|trip| trip.temp).map(|trip| trip_status(trip, true, unit))
+        }
+        _ => None,
+    };
+    Ok(Json(ThrottledResponse {
+        cpu_temp: temp.map(|temp| unit.convert_millis(temp)),
+        unit: unit.symbol(),
+        trips: trips
+            .iter()
+            .map(|trip| trip_status(trip, temp.is_some_and(|t| t >= trip.temp), unit))
+            .collect(),
+        active_trips: active.iter().map(|trip| trip.kind.clone()).collect(),
+        throttled: throttle.map(throttle_flags),
+        throttled_source: source,
+        throttled_at_trip: thermal_trip,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ThrottledResponse {
+    // In unit, as are the trip temperatures
+    cpu_temp: Option<f64>,
+    unit: &'static str,
+    trips: Vec<TripStatus>,
+    active_trips: Vec<String>,
+    throttled: Option<Throttle>,
+    // sysfs or vcgencmd
+    throttled_source: Option<&'static str>,
+    throttled_at_trip: Option<TripStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TripStatus {
+    index: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    temp: f64,
+    active: bool,
+}
+
+fn trip_status(trip: &Trip, active: bool, unit: TempUnit) -> TripStatus {
+    TripStatus {
+        index: trip.index,
+        kind: trip.kind.clone(),
+        temp: unit.convert_millis(trip.temp),
+        active,
+    }
+}
+
+// The throttle mask decoded, as nested flags and, for older clients, flat
+// *_now and *_occurred fields
+#[derive(Serialize, ToSchema)]
+pub struct Throttle {
+    raw: String,
+    now: ThrottleFlags,
+    since_boot: ThrottleFlags,
+    under_voltage_now: bool,
+    freq_capped_now: bool,
+    throttled_now: bool,
+    soft_temp_limit_now: bool,
+    under_voltage_occurred: bool,
+    freq_capped_occurred: bool,
+    throttled_occurred: bool,
+    soft_temp_limit_occurred: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ThrottleFlags {
+    under_voltage: bool,
+    freq_capped: bool,
+    throttled: bool,
+    soft_temp_limit: bool,
+}
+
+fn throttle_flags(mask: u32) -> Throttle {
+    let flags = |mask: u32| ThrottleFlags {
+        under_voltage: mask & UNDER_VOLTAGE != 0,
+        freq_capped: mask & FREQ_CAPPED != 0,
+        throttled: mask & THROTTLED != 0,
+        soft_temp_limit: mask & SOFT_TEMP_LIMIT != 0,
+    };
+    let set = |bit: u32| mask & bit != 0;
+    let occurred = |bit: u32| mask & (bit << OCCURRED_SHIFT) != 0;
+    Throttle {
+        raw: format!("0x{:x}", mask),
+        now: flags(mask),
+        since_boot: flags(mask >> OCCURRED_SHIFT),
+        under_voltage_now: set(UNDER_VOLTAGE),
+        freq_capped_now: set(FREQ_CAPPED),
+        throttled_now: set(THROTTLED),
+        soft_temp_limit_now: set(SOFT_TEMP_LIMIT),
+        under_voltage_occurred: occurred(UNDER_VOLTAGE),
+        freq_capped_occurred: occurred(FREQ_CAPPED),
+        throttled_occurred: occurred(THROTTLED),
+        soft_temp_limit_occurred: occurred(SOFT_TEMP_LIMIT),
+    }
+}
+
+fn read_trips(zone: &Path) -> Vec<Trip> {
+    // Trip points are numbered from 0 without gaps
+    let mut trips = Vec::new();
+    for index in 0.. {
+        let Some(temp) = read_i32(zone.join(format!("trip_point_{}_temp", index))) else {
+            break;
+        };
+        let kind = std::fs::read_to_string(zone.join(format!("trip_point_{}_type", index)))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        trips.push(Trip { index, kind, temp });
+    }
+    trace!("Read {} thermal trip points", trips.len());
+    trips
+}
+
+fn read_i32(path: impl AsRef<Path>) -> Option<i32> {
+    std::fs::read_to_string(path).ok()?.trim().parse::<i32>().ok()
+}
+
+async fn read_throttled(config: &Config) -> Result<(u32, &'static str), String> {
+    // Prefer the firmware driver's file, vcgencmd is slower and not always installed
+    if let Ok(contents) = tokio::fs::read_to_string(FIRMWARE_THROTTLED).await {
+        match parse_mask(&contents) {
+            Some(mask) => return Ok((mask, "sysfs")),
+            None => error!("Failed to parse {}: {:?}", FIRMWARE_THROTTLED, contents.trim()),
+        }
+    }
+    // Output looks like "throttled=0x50000"
+    let stdout = command::vcgencmd(config, &["get_throttled"]).await.map_err(|e| e.to_string())?;
+    command::vcgencmd_field(&stdout, "throttled")
+        .and_then(parse_mask)
+        .map(|mask| (mask, "vcgencmd"))
+        .ok_or_else(|| format!("Failed to parse vcgencmd output: {}", stdout.trim()))
+}
+
+fn parse_mask(hex: &str) -> Option<u32> {
+    // sysfs gives bare hex such as "50005", vcgencmd prefixes it with 0x
+    let hex = hex.trim();
+    u32::from_str_radix(hex.strip_prefix("0x").unwrap_or(hex), 16).ok()
+}
+
+#[utoipa::path(
+    get,
+    path = "/temps",
+    params(
+        ("unit" = Option<String>, Query, description = "c or f, Celsius by default"),
+    ),
+    responses(
+        (status = 200, description = "Temperature of every thermal zone", body = Vec<ZoneTemp>),
+        (status = 400, description = "Invalid query parameter", body = crate::error::ErrorBody),
+        (status = 500, description = "Reading failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_temps(Query(params): Query<HashMap<String, String>>) -> ApiResult<Vec<ZoneTemp>> {
+    // Every thermal zone the kernel exposes (CPU, GPU, PMIC...), zones that
+    // can't be read are left out
+    trace!("Reading all thermal zones for http request");
+    let unit = TempUnit::from_params(&params).map_err(ApiError::bad_request)?;
+    let zones = tokio::task::spawn_blocking(read_zones)
+        .await
+        .map_err(|e| ApiError::internal(format!("Thermal zone task failed: {}", e)))?
+        .map_err(ApiError::internal)?;
+    Ok(Json(
+        zones
+            .into_iter()
+            .map(|(zone, kind, temp)| ZoneTemp {
+                zone,
+                kind,
+                temp_celsius: temp as f64 / 1000.0,
+                temp: unit.convert_millis(temp),
+                unit: unit.symbol(),
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ZoneTemp {
+    zone: String,
+    #[serde(rename = "type")]
+    kind: String,
+    temp_celsius: f64,
+    // temp_celsius in the unit asked for
+    temp: f64,
+    unit: &'static str,
+}
+
+fn read_zones() -> Result<Vec<(String, String, i32)>, String> {
+    let entries = std::fs::read_dir(THERMAL_SYSFS).map_err(|e| format!("Failed to list thermal zones: {}", e))?;
+    let mut zones: Vec<(String, String, i32)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let zone = entry.file_name().to_str()?.to_string();
+            if !zone.starts_with("thermal_zone") {
+                return None;
+            }
+            let kind = std::fs::read_to_string(entry.path().join("type")).ok()?;
+            let temp = read_i32(entry.path().join("temp"))?;
+            Some((zone, kind.trim().to_string(), temp))
+        })
+        .collect();
+    // Sort numerically so thermal_zone10 comes after thermal_zone9
+    zones.sort_by_key(|(zone, _, _)| zone["thermal_zone".len()..].parse::<u32>().unwrap_or(u32::MAX));
+    Ok(zones)
+}
+
+#[utoipa::path(
+    get,
+    path = "/cooling",
+    responses(
+        (status = 200, description = "State of each cooling device", body = Vec<CoolingDevice>),
+        (status = 500, description = "Reading failed", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_cooling() -> ApiResult<Vec<CoolingDevice>> {
+    // The level each cooling device (fan, CPU frequency limit...) is commanded
+    // to, as opposed to the speed /fan_speed measures. Devices that can't be
+    // read are left out.
+    trace!("Reading cooling devices for http request");
+    let devices = tokio::task::spawn_blocking(|| read_cooling_devices(Path::new(THERMAL_SYSFS)))
+        .await
+        .map_err(|e| ApiError::internal(format!("Cooling device task failed: {}", e)))?
+        .map_err(ApiError::internal)?;
+    Ok(Json(devices))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CoolingDevice {
+    device: String,
+    #[serde(rename = "type")]
+    kind: String,
+    cur_state: i32,
+    max_state: i32,
+    // Null for devices without levels
+    level_percent: Option<f64>,
+}
+
+fn read_cooling_devices(root: &Path) -> Result<Vec<CoolingDevice>, String> {
+    let entries = std::fs::read_dir(root).map_err(|e| format!("Failed to list cooling devices: {}", e))?;
+    let mut devices: Vec<(u32, CoolingDevice)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            let index = name.strip_prefix("cooling_device")?.parse::<u32>().ok()?;
+            let kind = std::fs::read_to_string(entry.path().join("type")).ok()?;
+            let cur = read_i32(entry.path().join("cur_state"))?;
+            let max = read_i32(entry.path().join("max_state"))?;
+            let device = CoolingDevice {
+                device: name,
+                kind: kind.trim().to_string(),
+                cur_state: cur,
+                max_state: max,
+                level_percent: if max > 0 { Some(cur as f64 / max as f64 * 100.0) } else { None },
+            };
+            Some((index, device))
+        })
+        .collect();
+    // Sort numerically so cooling_device10 comes after cooling_device9
+    devices.sort_by_key(|(index, _)| *index);
+    Ok(devices.into_iter().map(|(_, device)| device).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_throttle_mask() {
+        assert_eq!(parse_mask("50005\n"), Some(0x50005));
+        assert_eq!(parse_mask("0x0"), Some(0));
+        assert_eq!(parse_mask("throttled"), None);
+        let flags = throttle_flags(0x50005);
+        assert!(flags.under_voltage_now);
+        assert!(flags.throttled_now);
+        assert!(!flags.freq_capped_now);
+        assert!(flags.under_voltage_occurred);
+        assert!(flags.throttled_occurred);
+        assert!(!flags.freq_capped_occurred);
+    }
+
+    #[test]
+    fn converts_temperature_units() {
+        let params = |unit: &str| HashMap::from([("unit".to_string(), unit.to_string())]);
+        let celsius = TempUnit::from_params(&HashMap::new()).unwrap();
+        assert_eq!(celsius.convert(48.123), 48.123);
+        assert_eq!(celsius.symbol(), "C");
+        let fahrenheit = TempUnit::from_params(&params("F")).unwrap();
+        assert_eq!(fahrenheit.convert(48.123), 118.621);
+        assert_eq!(fahrenheit.convert(-40.0), -40.0);
+        assert_eq!(fahrenheit.convert_millis(48123), 118.621);
+        assert_eq!(fahrenheit.symbol(), "F");
+        assert!(TempUnit::from_params(&params("kelvin")).is_err());
+    }
+
+    #[test]
+    fn reads_cooling_devices() {
+        let dir = crate::testing::temp_dir();
+        let root = dir.path();
+        for (name, kind, cur, max) in [("cooling_device10", "cpufreq-cpu0", "0", "0"), ("cooling_device0", "pwm-fan", "2", "4")] {
+            let dir = root.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("type"), format!("{}\n", kind)).unwrap();
+            std::fs::write(dir.join("cur_state"), cur).unwrap();
+            std::fs::write(dir.join("max_state"), max).unwrap();
+        }
+        // Unreadable devices and thermal zones are skipped
+        std::fs::create_dir_all(root.join("cooling_device2")).unwrap();
+        std::fs::create_dir_all(root.join("thermal_zone0")).unwrap();
+        let devices = read_cooling_devices(root).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].kind, "pwm-fan");
+        assert_eq!(devices[0].level_percent, Some(50.0));
+        assert_eq!(devices[1].device, "cooling_device10");
+        assert_eq!(devices[1].level_percent, None);
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::BTreeMap;
+
+use axum::Json;
+use log::{trace, warn};
+
+// Tunables under /proc/sys/vm that affect memory pressure and SD card writes
+const SYSCTLS: [&str; 5] = [
+    "swappiness",
+    "vfs_cache_pressure",
+    "dirty_ratio",
+    "dirty_background_ratio",
+    "dirty_expire_centisecs",
+];
+
+#[utoipa::path(
+    get,
+    path = "/vm",
+    responses(
+        (status = 200, description = "Kernel virtual memory settings such as swappiness", body = BTreeMap<String, Option<i64>>),
+    )
+)]
+pub async fn get_vm() -> Json<BTreeMap<&'static str, Option<i64>>> {
+    // Report each sysctl by name, null when the kernel doesn't expose it
+    trace!("Reading vm sysctls for http request");
+    let mut values = BTreeMap::new();
+    for name in SYSCTLS {
+        values.insert(name, read_sysctl(name).await);
+    }
+    Json(values)
+}
+
+async fn read_sysctl(name: &str) -> Option<i64> {
+    let path = format!("/proc/sys/vm/{}", name);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(value) => value.trim().parse::<i64>().ok(),
+        Err(e) => {
+            warn!("Failed to read {}: {}", path, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+
+use axum::Json;
+use axum::extract::State;
+use futures_util::future::join_all;
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::command;
+
+// Rails passed to `vcgencmd measure_volts`
+const VOLTAGE_RAILS: [&str; 4] = ["core", "sdram_c", "sdram_i", "sdram_p"];
+// Clocks passed to `vcgencmd measure_clock`
+const CLOCKS: [&str; 3] = ["arm", "core", "h264"];
+
+#[utoipa::path(
+    get,
+    path = "/voltages",
+    responses(
+        (status = 200, description = "Voltages and clock speeds from vcgencmd", body = VoltagesResponse),
+    )
+)]
+pub async fn get_voltages(State(state): State<AppState>) -> Json<VoltagesResponse> {
+    // Measured rail voltages and clock frequencies. All vcgencmd calls run at
+    // once, a reading that fails is null with its error under `errors`.
+    trace!("Measuring voltages and clocks for http request");
+    if let Some(cached) = state.metric_cache.get("voltages").and_then(|cached| serde_json::from_value(cached).ok()) {
+        return Json(cached);
+    }
+    let config = &state.config;
+    let (volts, clocks) = tokio::join!(
+        join_all(VOLTAGE_RAILS.map(|rail| async move { command::vcgencmd(config, &["measure_volts", rail]).await })),
+        join_all(CLOCKS.map(|clock| async move { command::vcgencmd(config, &["measure_clock", clock]).await })),
+    );
+    let mut errors = BTreeMap::new();
+    let volts = collect("volts", &VOLTAGE_RAILS, volts, parse_volts, &mut errors);
+    let clocks_hz = collect("clocks_hz", &CLOCKS, clocks, parse_clock_hz, &mut errors);
+    let measured = VoltagesResponse { volts, clocks_hz, errors };
+    // Partial readings are not cached, so a failed call is retried on the next request
+    if measured.errors.is_empty()
+        && let Ok(value) = serde_json::to_value(&measured)
+    {
+        state.metric_cache.insert("voltages".to_string(), value);
+    }
+    Json(measured)
+}
+
+// Readings by rail or clock name, a reading that failed is null with its error
+// in `errors` under e.g. volts.core
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct VoltagesResponse {
+    volts: BTreeMap<String, Option<f64>>,
+    clocks_hz: BTreeMap<String, Option<u64>>,
+    errors: BTreeMap<String, String>,
+}
+
+fn collect<T>(
+    kind: &str,
+    names: &[&str],
+    results: Vec<Result<String, command::CommandError>>,
+    parse: fn(&str) -> Option<T>,
+    errors: &mut BTreeMap<String, String>,
+) -> BTreeMap<String, Option<T>> {
+    let mut values = BTreeMap::new();
+    for (name, result) in names.iter().zip(results) {
+        let value = result.map_err(|e| e.to_string()).and_then(|stdout| {
+            parse(&stdout).ok_or_else(|| format!("Failed to parse vcgencmd output: {}", stdout.trim()))
+        });
+        let value = value
+            .inspect_err(|e| {
+                error!("Failed to measure {} {}: {}", kind, name, e);
+                errors.insert(format!("{}.{}", kind, name), e.clone());
+            })
+            .ok();
+        values.insert(name.to_string(), value);
+    }
+    values
+}
+
+fn parse_volts(stdout: &str) -> Option<f64> {
+    // "volt=1.2000V"
+    command::vcgencmd_field(stdout, "volt")?.strip_suffix('V')?.parse::<f64>().ok()
+}
+
+fn parse_clock_hz(stdout: &str) -> Option<u64> {
+    // "frequency(48)=1500398464", the number in brackets is the clock's id
+    let (key, hz) = stdout.trim().split_once('=')?;
+    if !key.starts_with("frequency(") {
+        return None;
+    }
+    hz.parse::<u64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_vcgencmd_measurements() {
+        assert_eq!(parse_volts("volt=1.2000V\n"), Some(1.2));
+        assert_eq!(parse_volts("volt=V\n"), None);
+        assert_eq!(parse_clock_hz("frequency(48)=1500398464\n"), Some(1500398464));
+        assert_eq!(parse_clock_hz("frequency(28)=0\n"), Some(0));
+        assert_eq!(parse_clock_hz("error=2 error_msg=\"Invalid arguments\""), None);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::Json;
+use axum::extract::Query;
+use log::trace;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::command;
+use crate::error::{ApiError, ApiResult};
+
+// Link quality most drivers, brcmfmac on the Pi included, report out of
+const MAX_QUALITY: f64 = 70.0;
+// Noise level drivers report when they can't measure it
+const NOISE_UNKNOWN: f64 = -256.0;
+const IWGETID_TIMEOUT: Duration = Duration::from_secs(2);
+
+struct Wireless {
+    interface: String,
+    link: f64,
+    level: f64,
+    noise: f64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/wifi",
+    params(
+        ("interface" = Option<String>, Query, description = "Wireless interface, the first one by default"),
+    ),
+    responses(
+        (status = 200, description = "Link quality, signal and noise with the SSID", body = WifiResponse),
+        (status = 404, description = "No wireless interface", body = crate::error::ErrorBody),
+    )
+)]
+pub async fn get_wifi(Query(params): Query<HashMap<String, String>>) -> ApiResult<WifiResponse> {
+    // Signal of a wireless interface, the first one unless ?interface= names it
+    trace!("Fetching wifi signal for http request");
+    let table = tokio::fs::read_to_string("/proc/net/wireless").await.unwrap_or_default();
+    let interfaces = parse_wireless(&table);
+    let wireless = match params.get("interface") {
+        Some(name) => interfaces.into_iter().find(|w| &w.interface == name),
+        None => interfaces.into_iter().next(),
+    }
+    .ok_or_else(|| ApiError::not_found("No wireless interface"))?;
+    // The SSID needs a wireless tool, it is null without one
+    let ssid = command::run("iwgetid", &["-r", &wireless.interface], IWGETID_TIMEOUT)
+        .await
+        .ok()
+        .map(|ssid| ssid.trim().to_string())
+        .filter(|ssid| !ssid.is_empty());
+    Ok(Json(WifiResponse {
+        ssid,
+        link_quality: wireless.link,
+        link_quality_percent: (wireless.link / MAX_QUALITY * 100.0).clamp(0.0, 100.0).round(),
+        signal_dbm: wireless.level,
+        noise_dbm: (wireless.noise != NOISE_UNKNOWN).then_some(wireless.noise),
+        interface: wireless.interface,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WifiResponse {
+    interface: String,
+    ssid: Option<String>,
+    link_quality: f64,
+    link_quality_percent: f64,
+    signal_dbm: f64,
+    // Null when the driver can't measure it
+    noise_dbm: Option<f64>,
+}
+
+fn parse_wireless(table: &str) -> Vec<Wireless> {
+    // Two header lines, then "wlan0: 0000   70.  -40.  -256  ..." per interface.
+    // Values may carry a trailing dot marking an updated reading.
+    table
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (interface, rest) = line.split_once(':')?;
+            let fields: Vec<f64> = rest
+                .split_whitespace()
+                .skip(1)
+                .take(3)
+                .map(|field| field.trim_end_matches('.').parse::<f64>().ok())
+                .collect::<Option<_>>()?;
+            Some(Wireless {
+                interface: interface.trim().to_string(),
+                link: *fields.first()?,
+                level: *fields.get(1)?,
+                noise: *fields.get(2)?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_proc_net_wireless() {
+        let table = "Inter-| sta-|   Quality        |   Discarded packets               | Missed | WE\n face | tus | link level noise |  nwid  crypt   frag  retry   misc | beacon | 22\n wlan0: 0000   54.  -56.  -256        0      0      0      0      0        0\n";
+        let interfaces = parse_wireless(table);
+        assert_eq!(interfaces.len(), 1);
+        assert_eq!(interfaces[0].interface, "wlan0");
+        assert_eq!(interfaces[0].link, 54.0);
+        assert_eq!(interfaces[0].level, -56.0);
+        assert_eq!(interfaces[0].noise, NOISE_UNKNOWN);
+        assert!(parse_wireless(&table[..table.find(" wlan0").unwrap()]).is_empty());
+    }
+}
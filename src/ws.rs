@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::Response;
+use log::{error, trace};
+use serde_json::Value;
+use tokio::time::{MissedTickBehavior, interval};
+
+use crate::AppState;
+use crate::stream::{DEFAULT_INTERVAL_SECS, Frame, MAX_INTERVAL_SECS, MIN_INTERVAL_SECS};
+
+// What a connection sends, changed by the client's messages
+#[derive(Debug, Clone, PartialEq)]
+struct Settings {
+    secs: u64,
+    // Collectors to send, every one when empty
+    subscribed: HashSet<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/ws",
+    params(
+        ("interval" = Option<u64>, Query, description = "Seconds between messages, 1 to 300, 2 by default"),
+    ),
+    responses(
+        (status = 101, description = "WebSocket sending every collector's value"),
+    )
+)]
+pub async fn get_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    // Same {timestamp, metrics} messages as /stream, with the interval and the
+    // collectors changeable over the socket. Everything runs in the upgraded
+    // connection's own task, which ends when the client disconnects.
+    let secs = params
+        .get("interval")
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECS)
+        .clamp(MIN_INTERVAL_SECS, MAX_INTERVAL_SECS);
+    let settings = Settings {
+        secs,
+        subscribed: HashSet::new(),
+    };
+    ws.on_upgrade(move |socket| serve(socket, state, settings))
+}
+
+async fn serve(mut socket: WebSocket, state: AppState, mut settings: Settings) {
+    trace!("WebSocket connected, sending metrics every {}s", settings.secs);
+    let names: Vec<&str> = state.collectors.iter().map(|collector| collector.name()).collect();
+    let mut ticker = new_ticker(settings.secs);
+    loop {
+        let reply = tokio::select! {
+            _ = ticker.tick() => collect(&state, &settings.subscribed).await,
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match apply(&text, &mut settings, &names) {
+                    Ok(change) => {
+                        if change.interval {
+                            trace!("WebSocket interval changed to {}s", settings.secs);
+                            // The new ticker's first tick sends right away
+                            ticker = new_ticker(settings.secs);
+                            continue;
+                        }
+                        if !change.refresh {
+                            continue;
+                        }
+                        collect(&state, &settings.subscribed).await
+                    }
+                    Err(e) => Frame::error(e),
+                },
+                // Pings are answered by axum, binary messages are ignored
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    trace!("WebSocket receive failed: {}", e);
+                    break;
+                }
+            },
+        };
+        if socket.send(Message::text(reply.to_text())).await.is_err() {
+            break;
+        }
+    }
+    trace!("WebSocket disconnected");
+}
+
+fn new_ticker(secs: u64) -> tokio::time::Interval {
+    let mut ticker = interval(Duration::from_secs(secs));
+    // A slow collection pushes the next message back instead of bursting
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker
+}
+
+async fn collect(state: &AppState, subscribed: &HashSet<String>) -> Frame {
+    let state = state.clone();
+    let subscribed = subscribed.clone();
+    // Collectors may block, cpu_usage sleeps between its two samples
+    let values = tokio::task::spawn_blocking(move || {
+        let registry = &state.collectors;
+        registry
+            .iter()
+            .filter(|collector| subscribed.is_empty() || subscribed.contains(collector.name()))
+            .filter(|collector| collector.supported())
+            .filter_map(|collector| Some((collector.name(), registry.collect(collector).ok()?)))
+            .collect::<HashMap<_, _>>()
+    })
+    .await;
+    match values {
+        Ok(values) => Frame::metrics(values),
+        Err(e) => {
+            error!("Collector task failed: {}", e);
+            Frame::error("Collector task failed")
+        }
+    }
+}
+
+// What a client message changed
+#[derive(Debug, PartialEq)]
+struct Change {
+    interval: bool,
+    refresh: bool,
+}
+
+// Apply a client message, a JSON object with any of `interval` (seconds),
+// `subscribe` (collector names, empty for all) and `refresh` (send now). An
+// invalid message changes nothing.
+fn apply(text: &str, current: &mut Settings, names: &[&str]) -> Result<Change, String> {
+    let mut settings = current.clone();
+    let message: Value = serde_json::from_str(text).map_err(|e| format!("Invalid message: {}", e))?;
+    let Value::Object(message) = message else {
+        return Err("Message must be a JSON object".to_string());
+    };
+    let mut change = Change {
+        interval: false,
+        refresh: false,
+    };
+    for (key, value) in message {
+        match key.as_str() {
+            "interval" => {
+                let secs = value.as_u64().ok_or("interval must be a whole number of seconds")?;
+                if !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&secs) {
+                    return Err(format!(
+                        "interval must be between {} and {} seconds",
+                        MIN_INTERVAL_SECS, MAX_INTERVAL_SECS
+                    ));
+                }
+                change.interval = secs != settings.secs;
+                settings.secs = secs;
+            }
+            "subscribe" => {
+                let subscribed = value
+                    .as_array()
+                    .ok_or("subscribe must be a list of collector names")?
+                    .iter()
+                    .map(|name| {
+                        let name = name.as_str().ok_or("subscribe must be a list of collector names")?;
+                        if !names.contains(&name) {
+                            return Err(format!("Unknown collector: {}", name));
+                        }
+                        Ok(name.to_string())
+                    })
+                    .collect::<Result<HashSet<String>, String>>()?;
+                settings.subscribed = subscribed;
+                // Send the new selection right away
+                change.refresh = true;
+            }
+            "refresh" => change.refresh |= value.as_bool().unwrap_or(false),
+            _ => return Err(format!("Unknown field: {}", key)),
+        }
+    }
+    *current = settings;
+    Ok(change)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_client_messages() {
+        let names = ["cpu_temp", "mem_usage"];
+        let mut settings = Settings {
+            secs: 2,
+            subscribed: HashSet::new(),
+        };
+        let change = apply(r#"{"interval": 5, "subscribe": ["cpu_temp"]}"#, &mut settings, &names).unwrap();
+        assert_eq!(change, Change { interval: true, refresh: true });
+        assert_eq!(settings.secs, 5);
+        assert!(settings.subscribed.contains("cpu_temp"));
+        assert_eq!(apply(r#"{"refresh": true}"#, &mut settings, &names).unwrap(), Change { interval: false, refresh: true });
+        assert!(apply(r#"{"interval": 0}"#, &mut settings, &names).is_err());
+        assert!(apply(r#"{"subscribe": ["gpu"]}"#, &mut settings, &names).is_err());
+        assert!(apply("[]", &mut settings, &names).is_err());
+        // A rejected message leaves the settings as they were
+        assert!(apply(r#"{"interval": 10, "subscribe": ["gpu"]}"#, &mut settings, &names).is_err());
+        assert_eq!(settings.secs, 5);
+    }
+}